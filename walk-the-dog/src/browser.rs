@@ -4,7 +4,10 @@ use wasm_bindgen::{
     closure::WasmClosure, closure::WasmClosureFnOnce, prelude::Closure, JsCast, JsValue,
 };
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{CanvasRenderingContext2d, Response, Document, HtmlCanvasElement, HtmlImageElement, Window};
+use web_sys::{
+    CanvasRenderingContext2d, Document, Gamepad, HtmlCanvasElement, HtmlImageElement, Response,
+    Storage, UrlSearchParams, Window,
+};
 
 #[allow(unused_macros)]
 macro_rules! log {
@@ -28,6 +31,9 @@ pub fn document() -> Result<Document> {
         .document()
         .ok_or_else(|| anyhow!("No document found"))
 }
+pub fn is_hidden() -> Result<bool> {
+    Ok(document()?.hidden())
+}
 pub fn canvas() -> Result<HtmlCanvasElement> {
     document()?
         .get_element_by_id("canvas")
@@ -45,6 +51,50 @@ pub fn context() -> Result<CanvasRenderingContext2d> {
         .map_err(|ctx| anyhow!("Failed to cast {:#?} to CanvasRenderingContext2d", ctx))
 }
 
+pub fn local_storage() -> Result<Storage> {
+    window()?
+        .local_storage()
+        .map_err(|err| anyhow!("Failed to access localStorage: {:#?}", err))?
+        .ok_or_else(|| anyhow!("No localStorage found"))
+}
+
+// Slots without a pad connected come back as null and are left out.
+pub fn gamepads() -> Result<Vec<Gamepad>> {
+    Ok(window()?
+        .navigator()
+        .get_gamepads()
+        .map_err(|err| anyhow!("Failed to read gamepads: {:#?}", err))?
+        .iter()
+        .filter_map(|pad| pad.dyn_into::<Gamepad>().ok())
+        .collect())
+}
+
+pub type ListenerClosure = Closure<dyn FnMut()>;
+
+// Listens during the capture phase, so nothing on the page can stop the event first.
+pub fn add_document_listener(event: &str, listener: &ListenerClosure) -> Result<()> {
+    document()?
+        .add_event_listener_with_callback_and_bool(event, listener.as_ref().unchecked_ref(), true)
+        .map_err(|err| anyhow!("Failed to listen for {}: {:#?}", event, err))
+}
+
+pub fn remove_document_listener(event: &str, listener: &ListenerClosure) -> Result<()> {
+    document()?
+        .remove_event_listener_with_callback_and_bool(event, listener.as_ref().unchecked_ref(), true)
+        .map_err(|err| anyhow!("Failed to stop listening for {}: {:#?}", event, err))
+}
+
+pub fn query_param(name: &str) -> Result<Option<String>> {
+    let search = window()?
+        .location()
+        .search()
+        .map_err(|err| anyhow!("Failed to read the query string: {:#?}", err))?;
+
+    Ok(UrlSearchParams::new_with_str(&search)
+        .map_err(|err| anyhow!("Failed to parse the query string: {:#?}", err))?
+        .get(name))
+}
+
 pub fn spawn_local<F>(future: F)
 where
     F: futures::Future<Output = ()> + 'static,
@@ -98,6 +148,14 @@ where
     Closure::once(fn_once)
 }
 
+// For callbacks the browser calls at most once, freed as soon as it has.
+pub fn closure_once_into_js<F, A, R>(fn_once: F) -> JsValue
+where
+    F: 'static + WasmClosureFnOnce<A, R>,
+{
+    Closure::once_into_js(fn_once)
+}
+
 pub type LoopClosure = Closure<dyn FnMut(f64)>;
 pub fn request_animation_frame(f: &LoopClosure) -> Result<i32> {
     window()?
@@ -105,6 +163,12 @@ pub fn request_animation_frame(f: &LoopClosure) -> Result<i32> {
         .map_err(|_| anyhow!("Failed to request animation frame"))
 }
 
+pub fn cancel_animation_frame(handle: i32) -> Result<()> {
+    window()?
+        .cancel_animation_frame(handle)
+        .map_err(|_| anyhow!("Failed to cancel animation frame"))
+}
+
 pub fn closure_wrap<T: WasmClosure + ?Sized>(data: Box<T>) -> Closure<T> {
     Closure::wrap(data)
 }
@@ -5,11 +5,15 @@ use futures::channel::{
     mpsc::{unbounded, UnboundedReceiver},
     oneshot::channel,
 };
-use serde::Deserialize;
-use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Mutex};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap, HashSet},
+    rc::Rc,
+    sync::Mutex,
+};
 use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
 use web_sys::{CanvasRenderingContext2d, HtmlImageElement};
-use web_sys::{AudioBuffer, AudioContext};
 
 #[derive(Deserialize, Clone)]
 pub struct SheetRect {
@@ -31,7 +35,7 @@ pub struct Sheet {
     pub frames: HashMap<String, Cell>,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Point {
     pub x: i16,
     pub y: i16,
@@ -41,15 +45,263 @@ impl Point {
     pub fn new(x: i16, y: i16) -> Self {
         Point { x, y }
     }
+
+    // The point alpha of the way from previous to here, for drawing between two updates.
+    pub fn interpolate(&self, previous: &Point, alpha: f32) -> Point {
+        let lerp = |from: i16, to: i16| from + ((to - from) as f32 * alpha).round() as i16;
+
+        Point {
+            x: lerp(previous.x, self.x),
+            y: lerp(previous.y, self.y),
+        }
+    }
+}
+
+// Opaque reference to a loaded image. The pixels live in the backend, only the size is needed
+// by gameplay code for bounding boxes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageHandle {
+    id: usize,
+    width: i16,
+    height: i16,
+}
+
+impl ImageHandle {
+    pub fn new(id: usize, width: i16, height: i16) -> Self {
+        ImageHandle { id, width, height }
+    }
+
+    pub fn width(&self) -> i16 {
+        self.width
+    }
+
+    pub fn height(&self) -> i16 {
+        self.height
+    }
+}
+
+// Opaque reference to a loaded sound, played through a `SoundPlayer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SoundHandle(usize);
+
+impl SoundHandle {
+    pub fn new(id: usize) -> Self {
+        SoundHandle(id)
+    }
+
+    pub fn id(&self) -> usize {
+        self.0
+    }
+}
+
+// A stretch of a sound file that plays as a sound of its own, in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioSprite {
+    pub offset: f64,
+    pub duration: f64,
+}
+
+// Where a sprite sits on its sheet, in seconds. Without a duration it runs to the end of the file.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct AudioSpriteBounds {
+    pub offset: f64,
+    #[serde(default)]
+    pub duration: Option<f64>,
+}
+
+impl AudioSpriteBounds {
+    // The sprite on a file `length` seconds long, as long as all of it is on the file.
+    pub fn sprite(&self, length: f64) -> Result<AudioSprite> {
+        let duration = self.duration.unwrap_or(length - self.offset);
+        if self.offset < 0.0 {
+            Err(anyhow!("starts before the file does"))
+        } else if duration <= 0.0 {
+            Err(anyhow!("is empty"))
+        } else if self.offset + duration > length {
+            Err(anyhow!("runs past the end of the file"))
+        } else {
+            Ok(AudioSprite {
+                offset: self.offset,
+                duration,
+            })
+        }
+    }
+}
+
+// Many short sounds packed into one file, so they're all fetched and decoded at once.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AudioSpriteSheet {
+    pub file: String,
+    pub sprites: HashMap<String, AudioSpriteBounds>,
+}
+
+// Every sound plays through one of these, and they all play through the master bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bus {
+    Master,
+    Music,
+    Sfx,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BusLevel {
+    // From 0 for silent to 1 for the sound as it was recorded
+    pub gain: f32,
+    pub muted: bool,
+}
+
+impl BusLevel {
+    pub fn new(gain: f32) -> Self {
+        BusLevel { gain, muted: false }
+    }
+
+    // Muting keeps the gain, so unmuting goes back to the same level.
+    pub fn volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.gain
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MixerSettings {
+    pub master: BusLevel,
+    pub music: BusLevel,
+    pub sfx: BusLevel,
+}
+
+impl Default for MixerSettings {
+    // The assets are mastered loud, this puts both buses at 0.01 overall.
+    fn default() -> Self {
+        MixerSettings {
+            master: BusLevel::new(0.1),
+            music: BusLevel::new(0.1),
+            sfx: BusLevel::new(0.1),
+        }
+    }
+}
+
+impl MixerSettings {
+    // Stored settings are kept in range the same way the ones set here are.
+    pub fn clamped(mut self) -> Self {
+        for bus in [Bus::Master, Bus::Music, Bus::Sfx] {
+            let gain = self.bus(bus).gain;
+            self.set_gain(bus, gain);
+        }
+        self
+    }
+
+    pub fn bus(&self, bus: Bus) -> &BusLevel {
+        match bus {
+            Bus::Master => &self.master,
+            Bus::Music => &self.music,
+            Bus::Sfx => &self.sfx,
+        }
+    }
+
+    pub fn bus_mut(&mut self, bus: Bus) -> &mut BusLevel {
+        match bus {
+            Bus::Master => &mut self.master,
+            Bus::Music => &mut self.music,
+            Bus::Sfx => &mut self.sfx,
+        }
+    }
+
+    pub fn set_gain(&mut self, bus: Bus, gain: f32) {
+        self.bus_mut(bus).gain = gain.clamp(0.0, 1.0);
+    }
+
+    pub fn set_muted(&mut self, bus: Bus, muted: bool) {
+        self.bus_mut(bus).muted = muted;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fade {
+    Linear,
+    // Sounds more natural for fading out, but never quite reaches silence on its own.
+    Exponential,
+}
+
+// One sound that's playing, or was. Volume is on top of its bus, from 0 to 1.
+pub trait Playback {
+    fn stop(&self) -> Result<()>;
+    fn pause(&self) -> Result<()>;
+    fn resume(&self) -> Result<()>;
+    fn set_volume(&self, volume: f32) -> Result<()>;
+    // Replaces any fade that hasn't finished yet.
+    fn fade_to(&self, volume: f32, seconds: f64, fade: Fade) -> Result<()>;
+    // Stopped or played to the end, a paused sound hasn't ended.
+    fn has_ended(&self) -> bool;
+}
+
+#[derive(Clone)]
+pub struct PlaybackHandle {
+    playback: Rc<dyn Playback>,
+}
+
+impl PlaybackHandle {
+    pub fn new(playback: Rc<dyn Playback>) -> Self {
+        PlaybackHandle { playback }
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        self.playback.stop()
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        self.playback.pause()
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        self.playback.resume()
+    }
+
+    pub fn set_volume(&self, volume: f32) -> Result<()> {
+        self.playback.set_volume(volume)
+    }
+
+    pub fn fade_to(&self, volume: f32, seconds: f64, fade: Fade) -> Result<()> {
+        self.playback.fade_to(volume, seconds, fade)
+    }
+
+    pub fn has_ended(&self) -> bool {
+        self.playback.has_ended()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioState {
+    // Browsers start audio suspended until the player interacts with the page,
+    // anything played until then is queued.
+    Locked,
+    Running,
+    Suspended,
+    // Shut down for good, nothing plays any more.
+    Closed,
+}
+
+pub trait SoundPlayer {
+    fn play_sound(&self, sound: &SoundHandle, bus: Bus) -> Result<PlaybackHandle>;
+    fn play_looping_sound(&self, sound: &SoundHandle, bus: Bus) -> Result<PlaybackHandle>;
+    fn suspend(&self) -> Result<()>;
+    fn resume(&self) -> Result<()>;
+    fn mix(&self) -> MixerSettings;
+    fn set_mix(&self, mix: &MixerSettings) -> Result<()>;
+    fn state(&self) -> AudioState;
+    // Releases the audio hardware and anything listening for the page, for when the game stops.
+    fn close(&self) -> Result<()>;
 }
 
 pub struct SpriteSheet {
-    pub image: HtmlImageElement,
+    pub image: ImageHandle,
     pub sheet: Sheet,
 }
 
 impl SpriteSheet {
-    pub fn new(image: HtmlImageElement, sheet: Sheet) -> Self {
+    pub fn new(image: ImageHandle, sheet: Sheet) -> Self {
         SpriteSheet { image, sheet }
     }
 
@@ -57,12 +309,12 @@ impl SpriteSheet {
         self.sheet.frames.get(name).cloned()
     }
 
-    pub fn draw(&self, renderer: &Renderer, source: &Rect, dest: &Rect) {
+    pub fn draw(&self, renderer: &dyn Render, source: &Rect, dest: &Rect) {
         renderer.draw_image(&self.image, source, dest);
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Rect {
     pub position: Point,
     pub width: i16,
@@ -98,6 +350,10 @@ impl Rect {
         self.position.x += x;
     }
 
+    pub fn offset_by(&self, x: i16, y: i16) -> Rect {
+        Rect::new_from_x_y(self.x() + x, self.y() + y, self.width, self.height)
+    }
+
     pub fn y(&self) -> i16 {
         self.position.y
     }
@@ -128,12 +384,55 @@ impl Rect {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl TextAlign {
+    fn as_str(&self) -> &str {
+        match self {
+            TextAlign::Left => "left",
+            TextAlign::Center => "center",
+            TextAlign::Right => "right",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextStyle {
+    pub font: String,
+    pub fill_color: String,
+    pub align: TextAlign,
+}
+
+impl TextStyle {
+    pub fn new(font: &str, fill_color: &str, align: TextAlign) -> Self {
+        TextStyle {
+            font: font.into(),
+            fill_color: fill_color.into(),
+            align,
+        }
+    }
+}
+
+pub trait Render {
+    fn clear(&self, rect: &Rect);
+    fn draw_image(&self, image: &ImageHandle, frame: &Rect, destination: &Rect);
+    fn draw_entire_image(&self, image: &ImageHandle, position: &Point);
+    fn draw_stroke_rect(&self, rect: &Rect);
+    fn fill_rect(&self, rect: &Rect, color: &str);
+    fn draw_text(&self, text: &str, position: &Point, style: &TextStyle);
+}
+
 pub struct Renderer {
     context: CanvasRenderingContext2d,
 }
 
-impl Renderer {
-    pub fn clear(&self, rect: &Rect) {
+impl Render for Renderer {
+    fn clear(&self, rect: &Rect) {
         self.context.clear_rect(
             rect.x().into(),
             rect.y().into(),
@@ -142,29 +441,36 @@ impl Renderer {
         );
     }
 
-    pub fn draw_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
-        self.context
-            .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
-                &image,
-                frame.x().into(),
-                frame.y().into(),
-                frame.width.into(),
-                frame.height.into(),
-                destination.x().into(),
-                destination.y().into(),
-                destination.width.into(),
-                destination.height.into(),
-            )
-            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+    fn draw_image(&self, image: &ImageHandle, frame: &Rect, destination: &Rect) {
+        with_image_element(image, |element| {
+            self.context
+                .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    element,
+                    frame.x().into(),
+                    frame.y().into(),
+                    frame.width.into(),
+                    frame.height.into(),
+                    destination.x().into(),
+                    destination.y().into(),
+                    destination.width.into(),
+                    destination.height.into(),
+                )
+        })
+        .expect("Drawing is throwing exceptions! Unrecoverable error.");
     }
 
-    pub fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) {
-        self.context
-            .draw_image_with_html_image_element(image, position.x.into(), position.y.into())
-            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+    fn draw_entire_image(&self, image: &ImageHandle, position: &Point) {
+        with_image_element(image, |element| {
+            self.context.draw_image_with_html_image_element(
+                element,
+                position.x.into(),
+                position.y.into(),
+            )
+        })
+        .expect("Drawing is throwing exceptions! Unrecoverable error.");
     }
 
-    pub fn draw_stroke_rect(&self, rect: &Rect) {
+    fn draw_stroke_rect(&self, rect: &Rect) {
         self.context.stroke_rect(
             rect.x().into(),
             rect.y().into(),
@@ -172,10 +478,149 @@ impl Renderer {
             rect.height.into(),
         );
     }
+
+    fn fill_rect(&self, rect: &Rect, color: &str) {
+        self.context.set_fill_style(&JsValue::from_str(color));
+        self.context.fill_rect(
+            rect.x().into(),
+            rect.y().into(),
+            rect.width.into(),
+            rect.height.into(),
+        );
+    }
+
+    fn draw_text(&self, text: &str, position: &Point, style: &TextStyle) {
+        self.context.set_font(&style.font);
+        self.context
+            .set_fill_style(&JsValue::from_str(&style.fill_color));
+        self.context.set_text_align(style.align.as_str());
+        self.context
+            .fill_text(text, position.x.into(), position.y.into())
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCall {
+    Clear(Rect),
+    Image {
+        image: ImageHandle,
+        source: Rect,
+        destination: Rect,
+    },
+    EntireImage {
+        image: ImageHandle,
+        position: Point,
+    },
+    StrokeRect(Rect),
+    FillRect {
+        rect: Rect,
+        color: String,
+    },
+    Text {
+        text: String,
+        position: Point,
+        style: TextStyle,
+    },
+}
+
+// Draws nothing, only records every call so tests can assert on what a frame would look like.
+#[cfg(test)]
+#[derive(Default)]
+pub struct HeadlessRenderer {
+    calls: RefCell<Vec<DrawCall>>,
+}
+
+#[cfg(test)]
+impl HeadlessRenderer {
+    pub fn new() -> Self {
+        HeadlessRenderer::default()
+    }
+
+    pub fn calls(&self) -> Vec<DrawCall> {
+        self.calls.borrow().clone()
+    }
+
+    pub fn clear_calls(&self) {
+        self.calls.borrow_mut().clear();
+    }
+
+    fn record(&self, call: DrawCall) {
+        self.calls.borrow_mut().push(call);
+    }
+}
+
+#[cfg(test)]
+impl Render for HeadlessRenderer {
+    fn clear(&self, rect: &Rect) {
+        self.record(DrawCall::Clear(*rect));
+    }
+
+    fn draw_image(&self, image: &ImageHandle, frame: &Rect, destination: &Rect) {
+        self.record(DrawCall::Image {
+            image: *image,
+            source: *frame,
+            destination: *destination,
+        });
+    }
+
+    fn draw_entire_image(&self, image: &ImageHandle, position: &Point) {
+        self.record(DrawCall::EntireImage {
+            image: *image,
+            position: *position,
+        });
+    }
+
+    fn draw_stroke_rect(&self, rect: &Rect) {
+        self.record(DrawCall::StrokeRect(*rect));
+    }
+
+    fn fill_rect(&self, rect: &Rect, color: &str) {
+        self.record(DrawCall::FillRect {
+            rect: *rect,
+            color: color.into(),
+        });
+    }
+
+    fn draw_text(&self, text: &str, position: &Point, style: &TextStyle) {
+        self.record(DrawCall::Text {
+            text: text.into(),
+            position: *position,
+            style: style.clone(),
+        });
+    }
+}
+
+// Images loaded by the browser backend, indexed by `ImageHandle::id`.
+thread_local! {
+    static IMAGE_ELEMENTS: RefCell<Vec<HtmlImageElement>> = const { RefCell::new(Vec::new()) };
+}
+
+fn register_image_element(element: HtmlImageElement) -> ImageHandle {
+    IMAGE_ELEMENTS.with(|elements| {
+        let mut elements = elements.borrow_mut();
+        let handle = ImageHandle::new(
+            elements.len(),
+            element.width() as i16,
+            element.height() as i16,
+        );
+        elements.push(element);
+        handle
+    })
+}
+
+fn with_image_element<R>(image: &ImageHandle, f: impl FnOnce(&HtmlImageElement) -> R) -> R {
+    IMAGE_ELEMENTS.with(|elements| {
+        f(elements
+            .borrow()
+            .get(image.id)
+            .expect("Image handle was not loaded by this backend"))
+    })
 }
 
 #[allow(unused_must_use)]
-pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
+pub async fn load_image(source: &str) -> Result<ImageHandle> {
     let image = browser::new_image()?;
 
     let (complete_tx, complete_rx) = channel::<Result<()>>();
@@ -199,179 +644,1765 @@ pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
 
     complete_rx.await??;
 
-    Ok(image)
+    Ok(register_image_element(image))
 }
 
+// Loads everything a game needs and hands back the scene it starts on.
 #[async_trait(?Send)]
 pub trait Game {
-    async fn initialize(&self) -> Result<Box<dyn Game>>;
-    fn update(&mut self, keystate: &KeyState);
-    fn draw(&self, renderer: &Renderer);
+    async fn initialize(&self) -> Result<Box<dyn Scene>>;
 }
 
-// Sixty Frames per second, converted to a frame length in milliseconds
-const FRAME_SIZE: f32 = 1.0 / 60.0 * 1000.0;
-pub struct GameLoop {
-    last_frame: f64,
-    accumulated_delta: f32,
+pub trait Scene {
+    // Called when the scene is pushed onto the stack, and when it's popped off it.
+    fn enter(&mut self) {}
+    fn exit(&mut self) {}
+    fn update(&mut self, keystate: &KeyState) -> SceneChange;
+    // alpha is how far the frame is between the last update and the next one, from 0 to 1.
+    fn draw(&self, renderer: &dyn Render, alpha: f32);
+    // Called whenever the loop stops or starts updating the game, e.g. to silence its audio.
+    fn set_paused(&mut self, _paused: bool) {}
+    // Called with the milliseconds the loop gave up on catching up with, after a long frame.
+    fn time_dropped(&mut self, _milliseconds: f32) {}
+    // Called once the loop has stopped for good, to silence and release whatever outlives it.
+    fn shutdown(&mut self) {}
 }
-type SharedLoopClosure = Rc<RefCell<Option<LoopClosure>>>;
-
-impl GameLoop {
-    pub async fn start(game: impl Game + 'static) -> Result<()> {
-        let mut keyevent_receiver = prepare_input()?;
-        let mut game = game.initialize().await?;
-
-        let mut game_loop = GameLoop {
-            last_frame: browser::now()?,
-            accumulated_delta: 0.0,
-        };
-
-        let renderer = Renderer {
-            context: browser::context()?,
-        };
 
-        let f: SharedLoopClosure = Rc::new(RefCell::new(None));
-        let g = f.clone();
+// What a scene wants done with the stack after its update.
+pub enum SceneChange {
+    Stay,
+    Push(Box<dyn Scene>),
+    Pop,
+    Replace(Box<dyn Scene>),
+}
 
-        let mut keystate = KeyState::new();
-        *g.borrow_mut() = Some(browser::create_raf_closure(move |perf: f64| {
-            process_input(&mut keystate, &mut keyevent_receiver);
+// Only the top scene is updated, the ones below it stay frozen until it's popped.
+// Every scene is drawn, bottom first, so a pause menu can draw over the game it paused.
+#[derive(Default)]
+pub struct SceneStack {
+    scenes: Vec<Box<dyn Scene>>,
+}
 
-            game_loop.accumulated_delta += (perf - game_loop.last_frame) as f32;
-            while game_loop.accumulated_delta > FRAME_SIZE {
-                game.update(&keystate);
-                game_loop.accumulated_delta -= FRAME_SIZE;
-            }
-            game_loop.last_frame = perf;
-            game.draw(&renderer);
+impl SceneStack {
+    pub fn new(scene: Box<dyn Scene>) -> Self {
+        let mut stack = SceneStack { scenes: vec![] };
+        stack.push(scene);
+        stack
+    }
 
-            browser::request_animation_frame(f.borrow().as_ref().unwrap()).unwrap();
-        }));
+    pub fn push(&mut self, mut scene: Box<dyn Scene>) {
+        scene.enter();
+        self.scenes.push(scene);
+    }
 
-        browser::request_animation_frame(
-            g.borrow()
-                .as_ref()
-                .ok_or_else(|| anyhow!("GameLoop: Loop is None"))?,
-        )?;
-        Ok(())
+    pub fn pop(&mut self) -> Option<Box<dyn Scene>> {
+        let mut scene = self.scenes.pop()?;
+        scene.exit();
+        Some(scene)
     }
-}
 
-pub struct KeyState {
-    pressed_keys: HashMap<String, web_sys::KeyboardEvent>,
-}
+    pub fn replace(&mut self, scene: Box<dyn Scene>) -> Option<Box<dyn Scene>> {
+        let replaced = self.pop();
+        self.push(scene);
+        replaced
+    }
 
-impl KeyState {
-    fn new() -> Self {
-        return KeyState {
-            pressed_keys: HashMap::new(),
+    pub fn update(&mut self, keystate: &KeyState) {
+        let change = match self.scenes.last_mut() {
+            Some(scene) => scene.update(keystate),
+            None => SceneChange::Stay,
         };
-    }
 
-    pub fn is_pressed(&self, code: &str) -> bool {
-        self.pressed_keys.contains_key(code)
+        match change {
+            SceneChange::Stay => {}
+            SceneChange::Push(scene) => self.push(scene),
+            SceneChange::Pop => {
+                self.pop();
+            }
+            SceneChange::Replace(scene) => {
+                self.replace(scene);
+            }
+        }
     }
 
-    fn set_pressed(&mut self, code: &str, event: web_sys::KeyboardEvent) {
-        self.pressed_keys.insert(code.into(), event);
+    // The ones below the top aren't updated, so they're drawn where their last update left
+    // them rather than between it and the next.
+    pub fn draw(&self, renderer: &dyn Render, alpha: f32) {
+        if let Some((top, below)) = self.scenes.split_last() {
+            below.iter().for_each(|scene| scene.draw(renderer, 1.0));
+            top.draw(renderer, alpha);
+        }
     }
 
-    fn set_released(&mut self, code: &str) {
-        self.pressed_keys.remove(code.into());
+    // Only the top scene is running, the ones below were paused by whatever covers them.
+    pub fn set_paused(&mut self, paused: bool) {
+        if let Some(scene) = self.scenes.last_mut() {
+            scene.set_paused(paused);
+        }
     }
-}
 
-enum KeyPress {
-    KeyUp(web_sys::KeyboardEvent),
-    KeyDown(web_sys::KeyboardEvent),
-}
+    pub fn time_dropped(&mut self, milliseconds: f32) {
+        if let Some(scene) = self.scenes.last_mut() {
+            scene.time_dropped(milliseconds);
+        }
+    }
 
-fn process_input(state: &mut KeyState, keyevent_receiver: &mut UnboundedReceiver<KeyPress>) {
-    loop {
-        match keyevent_receiver.try_next() {
-            Ok(None) => break,
-            Err(_err) => break,
-            Ok(Some(evt)) => match evt {
-                KeyPress::KeyUp(evt) => state.set_released(&evt.code()),
-                KeyPress::KeyDown(evt) => state.set_pressed(&evt.code(), evt),
-            },
-        };
+    // Top first, the way they'd be popped.
+    pub fn shutdown(&mut self) {
+        self.scenes
+            .iter_mut()
+            .rev()
+            .for_each(|scene| scene.shutdown());
     }
 }
 
-#[allow(unused_must_use)]
-fn prepare_input() -> Result<UnboundedReceiver<KeyPress>> {
-    let (keydown_sender, keyevent_receiver) = unbounded();
-    let keydown_sender = Rc::new(RefCell::new(keydown_sender));
-    let keyup_sender = Rc::clone(&keydown_sender);
-    let onkeydown = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
-        keydown_sender
-            .borrow_mut()
-            .start_send(KeyPress::KeyDown(keycode));
-    }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
-
-    let onkeyup = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
-        keyup_sender
-            .borrow_mut()
-            .start_send(KeyPress::KeyUp(keycode));
-    }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
-
-    browser::canvas()?.set_onkeydown(Some(onkeydown.as_ref().unchecked_ref()));
-    browser::canvas()?.set_onkeyup(Some(onkeyup.as_ref().unchecked_ref()));
-    onkeydown.forget();
-    onkeyup.forget();
+const PAUSE_OVERLAY_COLOR: &str = "rgba(0, 0, 0, 0.5)";
+const PAUSE_FONT: &str = "48px sans-serif";
+const PAUSE_TEXT_COLOR: &str = "#fff";
 
-    Ok(keyevent_receiver)
+// Pushed over the game to pause it, the audio is silent until it's popped again by one of
+// `resume_codes`.
+pub struct PauseScene {
+    screen: Rect,
+    resume_codes: Vec<String>,
+    audio: Rc<dyn SoundPlayer>,
 }
 
-pub struct Image {
-    element: HtmlImageElement,
-    pub bounding_box: Rect,
+impl PauseScene {
+    pub fn new(screen: Rect, resume_codes: Vec<String>, audio: Rc<dyn SoundPlayer>) -> Self {
+        PauseScene {
+            screen,
+            resume_codes,
+            audio,
+        }
+    }
 }
 
-impl Image {
-    pub fn new(element: HtmlImageElement, position: Point) -> Self {
-        let bounding_box = Rect::new(position, element.width() as i16, element.height() as i16);
-
-        Self {
-            element,
-            bounding_box,
+impl Scene for PauseScene {
+    fn enter(&mut self) {
+        if let Err(err) = self.audio.suspend() {
+            log!("Error pausing audio: {}", err);
         }
     }
 
-    pub fn draw(&self, renderer: &Renderer) {
-        // for debug
-        renderer.draw_stroke_rect(&self.bounding_box);
-
-        renderer.draw_entire_image(&self.element, &self.position());
+    fn exit(&mut self) {
+        if let Err(err) = self.audio.resume() {
+            log!("Error resuming audio: {}", err);
+        }
     }
 
-    pub fn move_horizontally(&mut self, distance: i16) {
-        self.bounding_box.add_x(distance);
-        self.position().x += distance;
+    fn update(&mut self, keystate: &KeyState) -> SceneChange {
+        if self
+            .resume_codes
+            .iter()
+            .any(|code| keystate.just_pressed(code))
+        {
+            SceneChange::Pop
+        } else {
+            SceneChange::Stay
+        }
     }
 
-    pub fn set_x(&mut self, x: i16) {
-        self.bounding_box.set_x(x);
-        self.position().x = x;
+    fn draw(&self, renderer: &dyn Render, _alpha: f32) {
+        renderer.fill_rect(&self.screen, PAUSE_OVERLAY_COLOR);
+        renderer.draw_text(
+            "Paused",
+            &Point::new(
+                self.screen.x() + self.screen.width / 2,
+                self.screen.y() + self.screen.height / 2,
+            ),
+            &TextStyle::new(PAUSE_FONT, PAUSE_TEXT_COLOR, TextAlign::Center),
+        );
     }
+}
 
-    pub fn right(&self) -> i16 {
-        self.bounding_box.right()
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameLoopConfig {
+    // Fixed updates per second
+    pub tick_rate: f32,
+    pub max_updates_per_frame: u32,
+    // Milliseconds a single frame may add, anything above that is dropped before updating
+    pub max_accumulated_delta: f32,
+    // How far a stick has to be pushed, from 0 to 1, before it counts as held
+    pub gamepad_deadzone: f64,
+}
+
+impl GameLoopConfig {
+    // Length of one fixed update in milliseconds
+    pub fn frame_size(&self) -> f32 {
+        1000.0 / self.tick_rate
     }
+}
 
-    pub fn position(&self) -> Point {
-        self.bounding_box.position
+impl Default for GameLoopConfig {
+    fn default() -> Self {
+        GameLoopConfig {
+            tick_rate: 60.0,
+            max_updates_per_frame: 10,
+            max_accumulated_delta: 250.0,
+            gamepad_deadzone: 0.25,
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct Audio {
-    context: AudioContext,
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FrameUpdates {
+    updates: u32,
+    dropped: f32,
 }
 
-#[derive(Clone)]
-pub struct Sound {
-    buffer: AudioBuffer,
+pub struct GameLoop {
+    config: GameLoopConfig,
+    last_frame: f64,
+    accumulated_delta: f32,
+    // Nothing is updated while the tab is hidden. The player pauses with a scene instead,
+    // so coming back to the tab doesn't resume a game they paused.
+    hidden: bool,
+}
+type KeyListener = Closure<dyn FnMut(web_sys::KeyboardEvent)>;
+
+// Everything the browser calls back into while the loop runs. The animation frame closure
+// holds on to this itself, so it lives until `GameLoopHandle::stop` takes it out.
+struct RunningLoop {
+    scenes: Rc<RefCell<SceneStack>>,
+    animation_frame: LoopClosure,
+    frame_id: std::cell::Cell<i32>,
+    _onkeydown: KeyListener,
+    _onkeyup: KeyListener,
+    _pointer_listeners: [PointerListener; 4],
+    _onvisibilitychange: Closure<dyn FnMut()>,
+}
+type SharedLoop = Rc<RefCell<Option<RunningLoop>>>;
+
+// Dropping the handle leaves the loop running, only `stop` tears it down.
+pub struct GameLoopHandle {
+    running: SharedLoop,
+}
+
+impl GameLoopHandle {
+    // Cancels the pending animation frame, detaches every listener the loop added and shuts
+    // the scenes down. Scenes never get the handle, so this always runs between frames.
+    // The listeners go first and every step runs whatever fails, so nothing is left calling
+    // into the closures once they're dropped. The first error is returned after that.
+    pub fn stop(self) -> Result<()> {
+        if let Some(running) = self.running.borrow_mut().take() {
+            let detached = detach_listeners();
+            let cancelled = browser::cancel_animation_frame(running.frame_id.get());
+            running.scenes.borrow_mut().shutdown();
+            detached.and(cancelled)?;
+        }
+        Ok(())
+    }
+}
+
+fn detach_listeners() -> Result<()> {
+    let canvas = browser::canvas().map(|canvas| {
+        canvas.set_onkeydown(None);
+        canvas.set_onkeyup(None);
+        canvas.set_onpointerdown(None);
+        canvas.set_onpointermove(None);
+        canvas.set_onpointerup(None);
+        canvas.set_onpointercancel(None);
+    });
+    let document = browser::document().map(|document| document.set_onvisibilitychange(None));
+    canvas.and(document)
+}
+
+impl GameLoop {
+    pub async fn start(
+        game: impl Game + 'static,
+        config: GameLoopConfig,
+    ) -> Result<GameLoopHandle> {
+        let (mut keyevent_receiver, onkeydown, onkeyup) = prepare_input()?;
+        let (mut pointer_receiver, pointer_listeners) = prepare_pointers()?;
+        let scenes = Rc::new(RefCell::new(SceneStack::new(game.initialize().await?)));
+
+        let game_loop = Rc::new(RefCell::new(GameLoop {
+            config,
+            last_frame: browser::now()?,
+            accumulated_delta: 0.0,
+            hidden: browser::is_hidden()?,
+        }));
+        let onvisibilitychange = prepare_visibility(scenes.clone(), game_loop.clone())?;
+
+        let renderer = Renderer {
+            context: browser::context()?,
+        };
+        let f: SharedLoop = Rc::new(RefCell::new(None));
+        let g = f.clone();
+
+        let mut keystate = KeyState::new();
+        let mut gamepad = GamepadInput::new(BrowserGamepads::default(), config.gamepad_deadzone);
+        let mut gestures = GestureRecognizer::new();
+        let running_scenes = scenes.clone();
+        let animation_frame = browser::create_raf_closure(move |perf: f64| {
+            let mut scenes = scenes.borrow_mut();
+            let mut game_loop = game_loop.borrow_mut();
+
+            process_input(&mut keystate, &mut keyevent_receiver);
+            process_pointers(&mut keystate, &mut pointer_receiver, &mut gestures);
+            gamepad.update(&mut keystate);
+
+            if game_loop.hidden {
+                // nothing would see them, and they'd fire on the first tick after resuming
+                keystate.end_tick();
+            } else {
+                let frame = game_loop.advance(perf);
+                update_scenes(&mut scenes, &mut keystate, frame.updates);
+                if frame.dropped > 0.0 {
+                    scenes.time_dropped(frame.dropped);
+                }
+            }
+            game_loop.last_frame = perf;
+            scenes.draw(
+                &renderer,
+                game_loop.accumulated_delta / game_loop.config.frame_size(),
+            );
+
+            if let Some(running) = f.borrow().as_ref() {
+                running
+                    .frame_id
+                    .set(browser::request_animation_frame(&running.animation_frame).unwrap());
+            }
+        });
+
+        *g.borrow_mut() = Some(RunningLoop {
+            scenes: running_scenes,
+            animation_frame,
+            frame_id: std::cell::Cell::new(0),
+            _onkeydown: onkeydown,
+            _onkeyup: onkeyup,
+            _pointer_listeners: pointer_listeners,
+            _onvisibilitychange: onvisibilitychange,
+        });
+
+        if let Some(running) = g.borrow().as_ref() {
+            running
+                .frame_id
+                .set(browser::request_animation_frame(&running.animation_frame)?);
+        }
+        Ok(GameLoopHandle { running: g })
+    }
+
+    // Adds the time since the last frame and works out how many fixed updates it's worth.
+    // Time over max_accumulated_delta, or left over once max_updates_per_frame is reached,
+    // is dropped instead of being carried into the next frame.
+    fn advance(&mut self, now: f64) -> FrameUpdates {
+        let frame_size = self.config.frame_size();
+        let mut dropped = 0.0;
+
+        self.accumulated_delta += (now - self.last_frame) as f32;
+        self.last_frame = now;
+        if self.accumulated_delta > self.config.max_accumulated_delta {
+            dropped += self.accumulated_delta - self.config.max_accumulated_delta;
+            self.accumulated_delta = self.config.max_accumulated_delta;
+        }
+
+        let mut updates = 0;
+        while self.accumulated_delta > frame_size && updates < self.config.max_updates_per_frame {
+            self.accumulated_delta -= frame_size;
+            updates += 1;
+        }
+
+        if self.accumulated_delta > frame_size {
+            let remainder = self.accumulated_delta % frame_size;
+            dropped += self.accumulated_delta - remainder;
+            self.accumulated_delta = remainder;
+        }
+
+        FrameUpdates { updates, dropped }
+    }
+
+    // Throws away the time spent hidden, so showing the tab doesn't run a burst of catch-up
+    // updates. What's left of the current update is kept, so interpolated drawing doesn't jump.
+    fn visibility_changed(&mut self, scenes: &mut SceneStack, hidden: bool, now: f64) {
+        if self.hidden != hidden {
+            self.hidden = hidden;
+            scenes.set_paused(hidden);
+            self.last_frame = now;
+        }
+    }
+}
+
+fn prepare_visibility(
+    scenes: Rc<RefCell<SceneStack>>,
+    game_loop: Rc<RefCell<GameLoop>>,
+) -> Result<Closure<dyn FnMut()>> {
+    let onvisibilitychange = browser::closure_wrap(Box::new(move || {
+        let mut game_loop = game_loop.borrow_mut();
+
+        match (browser::is_hidden(), browser::now()) {
+            (Ok(hidden), Ok(now)) => {
+                game_loop.visibility_changed(&mut scenes.borrow_mut(), hidden, now);
+            }
+            (Err(err), _) | (_, Err(err)) => {
+                error!("Error checking page visibility: {:#?}", err);
+            }
+        }
+    }) as Box<dyn FnMut()>);
+
+    browser::document()?.set_onvisibilitychange(Some(onvisibilitychange.as_ref().unchecked_ref()));
+
+    Ok(onvisibilitychange)
+}
+
+// Steps a game with fixed ticks and scripted input, without touching the browser.
+// The game stays at the bottom where it can be looked at, whatever it pushes goes on a stack
+// above it. A change it asks for to itself is ignored, there's nothing to go back to below it.
+#[cfg(test)]
+pub struct HeadlessGameLoop<G: Scene> {
+    game: G,
+    scenes: SceneStack,
+    config: GameLoopConfig,
+    ticks: u32,
+    keystate: KeyState,
+}
+
+#[cfg(test)]
+impl<G: Scene> HeadlessGameLoop<G> {
+    pub fn new(game: G) -> Self {
+        HeadlessGameLoop::with_config(game, GameLoopConfig::default())
+    }
+
+    pub fn with_config(game: G, config: GameLoopConfig) -> Self {
+        HeadlessGameLoop {
+            game,
+            scenes: SceneStack::default(),
+            config,
+            ticks: 0,
+            keystate: KeyState::new(),
+        }
+    }
+
+    pub fn step(&mut self, keystate: &KeyState) {
+        self.ticks += 1;
+        if !self.scenes.scenes.is_empty() {
+            self.scenes.update(keystate);
+        } else if let SceneChange::Push(scene) = self.game.update(keystate) {
+            self.scenes.push(scene);
+        }
+    }
+
+    // Holds `keystate`'s keys for every tick, so they're only just pressed on the first one.
+    pub fn run(&mut self, ticks: u32, keystate: &KeyState) {
+        self.run_scripted(ticks, |_| keystate.clone());
+    }
+
+    // The script receives the index of the tick it is producing input for, counted from zero,
+    // and returns the keys held on it. Keys go down and up the same way they do in GameLoop.
+    pub fn run_scripted(&mut self, ticks: u32, mut script: impl FnMut(u32) -> KeyState) {
+        for _ in 0..ticks {
+            // presses and releases whatever it takes for exactly the script's keys to be held
+            let mut keystate = std::mem::take(&mut self.keystate);
+            let held = script(self.ticks);
+            let released: Vec<String> = keystate
+                .pressed_keys
+                .difference(&held.pressed_keys)
+                .cloned()
+                .collect();
+            released.iter().for_each(|code| keystate.set_released(code));
+            held.pressed_keys
+                .iter()
+                .for_each(|code| keystate.set_pressed(code));
+            self.step(&keystate);
+            keystate.end_tick();
+            self.keystate = keystate;
+        }
+    }
+
+    // Steps once for every recorded tick, with exactly the input it was recorded with.
+    pub fn replay(&mut self, recording: &InputRecording) {
+        recording.keystates().for_each(|keystate| {
+            self.step(keystate);
+        });
+    }
+
+    // Draws the state after the last step, with nothing interpolated.
+    pub fn draw(&self, renderer: &dyn Render) {
+        self.draw_interpolated(renderer, 1.0);
+    }
+
+    // Only the top scene is interpolated, the same as SceneStack does.
+    pub fn draw_interpolated(&self, renderer: &dyn Render, alpha: f32) {
+        if self.scenes.scenes.is_empty() {
+            self.game.draw(renderer, alpha);
+        } else {
+            self.game.draw(renderer, 1.0);
+            self.scenes.draw(renderer, alpha);
+        }
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    pub fn elapsed(&self) -> f32 {
+        self.ticks as f32 * self.config.frame_size()
+    }
+
+    pub fn game(&self) -> &G {
+        &self.game
+    }
+
+    pub fn game_mut(&mut self) -> &mut G {
+        &mut self.game
+    }
+}
+
+// Held keys, plus the ones that went down or up since the last tick.
+// The transitions stay put until a tick has seen them, so a frame with no updates
+// doesn't lose them and a frame with several updates only reports them once.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyState {
+    #[serde(default, rename = "held", skip_serializing_if = "BTreeSet::is_empty")]
+    pressed_keys: BTreeSet<String>,
+    #[serde(default, rename = "down", skip_serializing_if = "BTreeSet::is_empty")]
+    just_pressed: BTreeSet<String>,
+    #[serde(default, rename = "up", skip_serializing_if = "BTreeSet::is_empty")]
+    just_released: BTreeSet<String>,
+}
+
+impl KeyState {
+    pub fn new() -> Self {
+        KeyState::default()
+    }
+
+    #[cfg(test)]
+    pub fn with_pressed(codes: &[&str]) -> Self {
+        let mut state = KeyState::new();
+        codes.iter().for_each(|code| state.set_pressed(code));
+        state
+    }
+
+    pub fn is_pressed(&self, code: &str) -> bool {
+        self.pressed_keys.contains(code)
+    }
+
+    pub fn just_pressed(&self, code: &str) -> bool {
+        self.just_pressed.contains(code)
+    }
+
+    pub fn just_pressed_codes(&self) -> impl Iterator<Item = &str> {
+        self.just_pressed.iter().map(String::as_str)
+    }
+
+    #[cfg(test)]
+    pub fn just_released(&self, code: &str) -> bool {
+        self.just_released.contains(code)
+    }
+
+    // A key that's already held doesn't count as pressed again, which is what key repeat sends.
+    pub fn set_pressed(&mut self, code: &str) {
+        if self.pressed_keys.insert(code.into()) {
+            self.just_pressed.insert(code.into());
+        }
+    }
+
+    pub fn set_released(&mut self, code: &str) {
+        if self.pressed_keys.remove(code) {
+            self.just_released.insert(code.into());
+        }
+    }
+
+    // Called once a tick has seen the transitions.
+    pub fn end_tick(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+}
+
+// A snapshot of one pad. Buttons and axes are in the order the browser reports them,
+// which for the standard mapping puts A at 0, Start at 9 and the d-pad at 12 to 15.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GamepadState {
+    pub buttons: Vec<bool>,
+    pub axes: Vec<f64>,
+}
+
+pub trait Gamepads {
+    fn poll(&self) -> Vec<GamepadState>;
+}
+
+// A page that isn't allowed gamepads fails every poll the same way, so after the first
+// failure is logged there's no more polling.
+#[derive(Default)]
+pub struct BrowserGamepads {
+    failed: std::cell::Cell<bool>,
+}
+
+impl Gamepads for BrowserGamepads {
+    fn poll(&self) -> Vec<GamepadState> {
+        if self.failed.get() {
+            return vec![];
+        }
+
+        let pads = browser::gamepads().unwrap_or_else(|err| {
+            error!("Error polling gamepads, they won't be read: {:#?}", err);
+            self.failed.set(true);
+            vec![]
+        });
+
+        pads.iter()
+            .map(|pad| GamepadState {
+                buttons: pad
+                    .buttons()
+                    .iter()
+                    .map(|button| button.unchecked_into::<web_sys::GamepadButton>().pressed())
+                    .collect(),
+                axes: pad
+                    .axes()
+                    .iter()
+                    .map(|axis| axis.as_f64().unwrap_or(0.0))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+// Pads you set by hand, for tests. Clones share their pads, so a test can keep one to set
+// them while GamepadInput polls another.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct HeadlessGamepads {
+    pads: Rc<RefCell<Vec<GamepadState>>>,
+}
+
+#[cfg(test)]
+impl HeadlessGamepads {
+    pub fn new() -> Self {
+        HeadlessGamepads::default()
+    }
+
+    pub fn set(&self, pads: Vec<GamepadState>) {
+        *self.pads.borrow_mut() = pads;
+    }
+}
+
+#[cfg(test)]
+impl Gamepads for HeadlessGamepads {
+    fn poll(&self) -> Vec<GamepadState> {
+        self.pads.borrow().clone()
+    }
+}
+
+// Turns polled pads into key codes in KeyState, so they can be bound like any key:
+// GamepadButton0 for a button and GamepadAxis1+ or GamepadAxis1- for a stick pushed
+// past the deadzone. Every pad shares the same codes.
+pub struct GamepadInput<P: Gamepads> {
+    gamepads: P,
+    deadzone: f64,
+    held: HashSet<String>,
+}
+
+impl<P: Gamepads> GamepadInput<P> {
+    pub fn new(gamepads: P, deadzone: f64) -> Self {
+        GamepadInput {
+            gamepads,
+            deadzone,
+            held: HashSet::new(),
+        }
+    }
+
+    // Returns the codes that went down since the last poll.
+    pub fn update(&mut self, keystate: &mut KeyState) -> Vec<String> {
+        let held: HashSet<String> = self
+            .gamepads
+            .poll()
+            .iter()
+            .flat_map(|pad| self.held_codes(pad))
+            .collect();
+
+        self.held
+            .difference(&held)
+            .for_each(|code| keystate.set_released(code));
+        let mut pressed: Vec<String> = held.difference(&self.held).cloned().collect();
+        pressed.sort();
+        pressed.iter().for_each(|code| keystate.set_pressed(code));
+
+        self.held = held;
+        pressed
+    }
+
+    fn held_codes(&self, pad: &GamepadState) -> Vec<String> {
+        let buttons = pad
+            .buttons
+            .iter()
+            .enumerate()
+            .filter(|(_, pressed)| **pressed)
+            .map(|(index, _)| format!("GamepadButton{}", index));
+        let axes = pad.axes.iter().enumerate().filter_map(|(index, value)| {
+            if *value > self.deadzone {
+                Some(format!("GamepadAxis{}+", index))
+            } else if *value < -self.deadzone {
+                Some(format!("GamepadAxis{}-", index))
+            } else {
+                None
+            }
+        });
+        buttons.chain(axes).collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointerPhase {
+    Down,
+    Move,
+    Up,
+    Cancel,
+}
+
+// One pointer event, with its position in CSS pixels and time in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointerInput {
+    pub phase: PointerPhase,
+    pub id: i32,
+    pub x: f64,
+    pub y: f64,
+    pub time: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    Tap,
+    SwipeDown,
+}
+
+impl Gesture {
+    // The key code the gesture presses and releases in KeyState, for binding it to an action.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Gesture::Tap => "Tap",
+            Gesture::SwipeDown => "SwipeDown",
+        }
+    }
+}
+
+const TAP_MAX_DISTANCE: f64 = 20.0;
+const TAP_MAX_DURATION: f64 = 300.0;
+const SWIPE_MIN_DISTANCE: f64 = 50.0;
+
+struct Touch {
+    start: PointerInput,
+    swiped: bool,
+}
+
+// Follows the last pointer to go down. A Down while one is followed starts over with the new
+// one, so a lift or cancel that never arrived can't leave every later gesture ignored.
+// A swipe is reported as soon as it's far enough, without waiting for the pointer to come up.
+#[derive(Default)]
+pub struct GestureRecognizer {
+    touch: Option<Touch>,
+}
+
+impl GestureRecognizer {
+    pub fn new() -> Self {
+        GestureRecognizer::default()
+    }
+
+    pub fn handle(&mut self, input: &PointerInput) -> Option<Gesture> {
+        if input.phase == PointerPhase::Down {
+            self.touch = Some(Touch {
+                start: *input,
+                swiped: false,
+            });
+            return None;
+        }
+
+        let touch = self
+            .touch
+            .as_mut()
+            .filter(|touch| touch.start.id == input.id)?;
+        let dx = input.x - touch.start.x;
+        let dy = input.y - touch.start.y;
+        let swipe = !touch.swiped && dy >= SWIPE_MIN_DISTANCE && dy > dx.abs();
+
+        match input.phase {
+            PointerPhase::Down => None,
+            PointerPhase::Move => {
+                if swipe {
+                    touch.swiped = true;
+                    Some(Gesture::SwipeDown)
+                } else {
+                    None
+                }
+            }
+            PointerPhase::Up => {
+                let swiped = touch.swiped;
+                let duration = input.time - touch.start.time;
+                self.touch = None;
+
+                if swipe {
+                    Some(Gesture::SwipeDown)
+                } else if !swiped
+                    && dx.hypot(dy) <= TAP_MAX_DISTANCE
+                    && duration <= TAP_MAX_DURATION
+                {
+                    Some(Gesture::Tap)
+                } else {
+                    None
+                }
+            }
+            PointerPhase::Cancel => {
+                self.touch = None;
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct InputRun {
+    ticks: u32,
+    keys: KeyState,
+}
+
+// Every KeyState a game was updated with, tick by tick, along with the seed it was
+// started with. Ticks in a row with the same input are stored once, and most are:
+// nothing pressed, or the same keys held.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputRecording {
+    seed: u64,
+    runs: Vec<InputRun>,
+}
+
+impl InputRecording {
+    pub fn new(seed: u64) -> Self {
+        InputRecording { seed, runs: vec![] }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn record(&mut self, keystate: &KeyState) {
+        match self.runs.last_mut() {
+            Some(run) if run.keys == *keystate => run.ticks += 1,
+            _ => self.runs.push(InputRun {
+                ticks: 1,
+                keys: keystate.clone(),
+            }),
+        }
+    }
+
+    pub fn keystates(&self) -> impl Iterator<Item = &KeyState> {
+        self.runs
+            .iter()
+            .flat_map(|run| (0..run.ticks).map(move |_| &run.keys))
+    }
+}
+
+enum KeyPress {
+    KeyUp(web_sys::KeyboardEvent),
+    KeyDown(web_sys::KeyboardEvent),
+}
+
+// Transitions only show up on the first of the frame's updates.
+fn update_scenes(scenes: &mut SceneStack, keystate: &mut KeyState, updates: u32) {
+    for _ in 0..updates {
+        scenes.update(keystate);
+        keystate.end_tick();
+    }
+}
+
+fn process_input(state: &mut KeyState, keyevent_receiver: &mut UnboundedReceiver<KeyPress>) {
+    loop {
+        match keyevent_receiver.try_next() {
+            Ok(None) => break,
+            Err(_err) => break,
+            Ok(Some(evt)) => match evt {
+                KeyPress::KeyUp(evt) => state.set_released(&evt.code()),
+                KeyPress::KeyDown(evt) => {
+                    if !evt.repeat() {
+                        state.set_pressed(&evt.code());
+                    }
+                }
+            },
+        };
+    }
+}
+
+// Gestures are over the moment they're recognized, so they're pressed and released at once
+// and only show up as just pressed.
+fn process_pointers(
+    state: &mut KeyState,
+    pointer_receiver: &mut UnboundedReceiver<PointerInput>,
+    gestures: &mut GestureRecognizer,
+) {
+    while let Ok(Some(input)) = pointer_receiver.try_next() {
+        if let Some(gesture) = gestures.handle(&input) {
+            state.set_pressed(gesture.code());
+            state.set_released(gesture.code());
+        }
+    }
+}
+
+#[allow(unused_must_use)]
+fn prepare_input() -> Result<(UnboundedReceiver<KeyPress>, KeyListener, KeyListener)> {
+    let (keydown_sender, keyevent_receiver) = unbounded();
+    let keydown_sender = Rc::new(RefCell::new(keydown_sender));
+    let keyup_sender = Rc::clone(&keydown_sender);
+    let onkeydown = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
+        keydown_sender
+            .borrow_mut()
+            .start_send(KeyPress::KeyDown(keycode));
+    }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
+
+    let onkeyup = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
+        keyup_sender
+            .borrow_mut()
+            .start_send(KeyPress::KeyUp(keycode));
+    }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
+
+    browser::canvas()?.set_onkeydown(Some(onkeydown.as_ref().unchecked_ref()));
+    browser::canvas()?.set_onkeyup(Some(onkeyup.as_ref().unchecked_ref()));
+
+    Ok((keyevent_receiver, onkeydown, onkeyup))
+}
+
+type PointerListener = Closure<dyn FnMut(web_sys::PointerEvent)>;
+
+fn prepare_pointers() -> Result<(UnboundedReceiver<PointerInput>, [PointerListener; 4])> {
+    let (sender, pointer_receiver) = unbounded();
+    let sender = Rc::new(RefCell::new(sender));
+    let canvas = browser::canvas()?;
+    let listener = |phase: PointerPhase| -> PointerListener {
+        let sender = Rc::clone(&sender);
+        let canvas = canvas.clone();
+        browser::closure_wrap(Box::new(move |evt: web_sys::PointerEvent| {
+            // keeps a touch from also scrolling or zooming the page
+            evt.prevent_default();
+            // so the Up still reaches the canvas when the pointer is let go outside of it
+            if phase == PointerPhase::Down {
+                if let Err(err) = canvas.set_pointer_capture(evt.pointer_id()) {
+                    error!("Error capturing the pointer: {:#?}", err);
+                }
+            }
+            let _ = sender.borrow_mut().start_send(PointerInput {
+                phase,
+                id: evt.pointer_id(),
+                x: evt.offset_x() as f64,
+                y: evt.offset_y() as f64,
+                time: evt.time_stamp(),
+            });
+        }) as Box<dyn FnMut(web_sys::PointerEvent)>)
+    };
+    let listeners = [
+        listener(PointerPhase::Down),
+        listener(PointerPhase::Move),
+        listener(PointerPhase::Up),
+        listener(PointerPhase::Cancel),
+    ];
+
+    canvas.set_onpointerdown(Some(listeners[0].as_ref().unchecked_ref()));
+    canvas.set_onpointermove(Some(listeners[1].as_ref().unchecked_ref()));
+    canvas.set_onpointerup(Some(listeners[2].as_ref().unchecked_ref()));
+    canvas.set_onpointercancel(Some(listeners[3].as_ref().unchecked_ref()));
+
+    Ok((pointer_receiver, listeners))
+}
+
+pub struct Image {
+    element: ImageHandle,
+    pub bounding_box: Rect,
+    previous_position: Point,
+}
+
+impl Image {
+    pub fn new(element: ImageHandle, position: Point) -> Self {
+        let bounding_box = Rect::new(position, element.width(), element.height());
+
+        Self {
+            element,
+            bounding_box,
+            previous_position: position,
+        }
+    }
+
+    pub fn draw(&self, renderer: &dyn Render, alpha: f32) {
+        let position = self.position().interpolate(&self.previous_position, alpha);
+
+        // for debug
+        renderer.draw_stroke_rect(&Rect::new(
+            position,
+            self.bounding_box.width,
+            self.bounding_box.height,
+        ));
+
+        renderer.draw_entire_image(&self.element, &position);
+    }
+
+    // Remembers where the image was before an update moves it, to draw in between.
+    pub fn save_position(&mut self) {
+        self.previous_position = self.position();
+    }
+
+    pub fn move_horizontally(&mut self, distance: i16) {
+        self.bounding_box.add_x(distance);
+        self.position().x += distance;
+    }
+
+    // Jumps straight to x, without drawing it sliding there from the previous position.
+    pub fn set_x(&mut self, x: i16) {
+        self.bounding_box.set_x(x);
+        self.position().x = x;
+        self.previous_position = self.position();
+    }
+
+    pub fn right(&self) -> i16 {
+        self.bounding_box.right()
+    }
+
+    pub fn position(&self) -> Point {
+        self.bounding_box.position
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackStatus {
+    // Played while the audio was locked, waiting for it to be unlocked
+    Queued,
+    Playing,
+    Paused,
+    Stopped,
+}
+
+// What a headless playback was last told to do.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeadlessPlayback {
+    pub sound: SoundHandle,
+    pub bus: Bus,
+    pub looping: bool,
+    pub status: PlaybackStatus,
+    pub volume: f32,
+    // Target volume, length in seconds and curve of the last fade
+    pub fade: Option<(f32, f64, Fade)>,
+}
+
+#[cfg(test)]
+impl Playback for RefCell<HeadlessPlayback> {
+    fn stop(&self) -> Result<()> {
+        self.borrow_mut().status = PlaybackStatus::Stopped;
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<()> {
+        let mut playback = self.borrow_mut();
+        if playback.status == PlaybackStatus::Playing {
+            playback.status = PlaybackStatus::Paused;
+        }
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<()> {
+        let mut playback = self.borrow_mut();
+        if playback.status == PlaybackStatus::Paused {
+            playback.status = PlaybackStatus::Playing;
+        }
+        Ok(())
+    }
+
+    fn set_volume(&self, volume: f32) -> Result<()> {
+        let mut playback = self.borrow_mut();
+        playback.volume = volume;
+        playback.fade = None;
+        Ok(())
+    }
+
+    fn fade_to(&self, volume: f32, seconds: f64, fade: Fade) -> Result<()> {
+        self.borrow_mut().fade = Some((volume, seconds, fade));
+        Ok(())
+    }
+
+    // Headless sounds have no length, they only end when they're stopped.
+    fn has_ended(&self) -> bool {
+        self.borrow().status == PlaybackStatus::Stopped
+    }
+}
+
+// Plays nothing, only records which sounds were requested and what happened to them.
+#[cfg(test)]
+#[derive(Default)]
+pub struct HeadlessAudio {
+    playbacks: RefCell<Vec<Rc<RefCell<HeadlessPlayback>>>>,
+    suspended: std::cell::Cell<bool>,
+    locked: std::cell::Cell<bool>,
+    closed: std::cell::Cell<bool>,
+    mix: std::cell::Cell<MixerSettings>,
+}
+
+#[cfg(test)]
+impl HeadlessAudio {
+    pub fn new() -> Self {
+        HeadlessAudio::default()
+    }
+
+    pub fn playbacks(&self) -> Vec<HeadlessPlayback> {
+        self.playbacks
+            .borrow()
+            .iter()
+            .map(|playback| *playback.borrow())
+            .collect()
+    }
+
+    pub fn played_on(&self, bus: Bus) -> Vec<SoundHandle> {
+        self.playbacks()
+            .iter()
+            .filter(|playback| playback.bus == bus)
+            .map(|playback| playback.sound)
+            .collect()
+    }
+
+    fn start(&self, sound: &SoundHandle, bus: Bus, looping: bool) -> PlaybackHandle {
+        let playback = Rc::new(RefCell::new(HeadlessPlayback {
+            sound: *sound,
+            bus,
+            looping,
+            status: if self.locked.get() {
+                PlaybackStatus::Queued
+            } else {
+                PlaybackStatus::Playing
+            },
+            volume: 1.0,
+            fade: None,
+        }));
+        self.playbacks.borrow_mut().push(playback.clone());
+        PlaybackHandle::new(playback)
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.get()
+    }
+
+    // Stands in for a browser that hasn't seen a user gesture yet. Unlocking starts
+    // whatever was played in the meantime and hasn't been stopped.
+    pub fn set_locked(&self, locked: bool) {
+        self.locked.set(locked);
+        if !locked {
+            self.playbacks
+                .borrow()
+                .iter()
+                .filter(|playback| playback.borrow().status == PlaybackStatus::Queued)
+                .for_each(|playback| playback.borrow_mut().status = PlaybackStatus::Playing);
+        }
+    }
+}
+
+#[cfg(test)]
+impl SoundPlayer for HeadlessAudio {
+    fn play_sound(&self, sound: &SoundHandle, bus: Bus) -> Result<PlaybackHandle> {
+        Ok(self.start(sound, bus, false))
+    }
+
+    fn play_looping_sound(&self, sound: &SoundHandle, bus: Bus) -> Result<PlaybackHandle> {
+        Ok(self.start(sound, bus, true))
+    }
+
+    fn suspend(&self) -> Result<()> {
+        self.suspended.set(true);
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<()> {
+        self.suspended.set(false);
+        Ok(())
+    }
+
+    fn mix(&self) -> MixerSettings {
+        self.mix.get()
+    }
+
+    fn set_mix(&self, mix: &MixerSettings) -> Result<()> {
+        self.mix.set(*mix);
+        Ok(())
+    }
+
+    fn state(&self) -> AudioState {
+        if self.closed.get() {
+            AudioState::Closed
+        } else if self.locked.get() {
+            AudioState::Locked
+        } else if self.suspended.get() {
+            AudioState::Suspended
+        } else {
+            AudioState::Running
+        }
+    }
+
+    fn close(&self) -> Result<()> {
+        self.closed.set(true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headless_renderer_records_draw_calls_in_order() {
+        let renderer = HeadlessRenderer::new();
+        let rect = Rect::new_from_x_y(10, 20, 30, 40);
+
+        renderer.clear(&Rect::new_from_x_y(0, 0, 600, 600));
+        renderer.draw_stroke_rect(&rect);
+
+        assert_eq!(
+            renderer.calls(),
+            vec![
+                DrawCall::Clear(Rect::new_from_x_y(0, 0, 600, 600)),
+                DrawCall::StrokeRect(rect),
+            ]
+        );
+
+        renderer.clear_calls();
+        assert!(renderer.calls().is_empty());
+    }
+
+    #[derive(Default)]
+    struct CountingGame {
+        updates: u32,
+        right_presses: u32,
+    }
+
+    impl Scene for CountingGame {
+        fn update(&mut self, keystate: &KeyState) -> SceneChange {
+            self.updates += 1;
+            if keystate.is_pressed("ArrowRight") {
+                self.right_presses += 1;
+            }
+            SceneChange::Stay
+        }
+
+        fn draw(&self, renderer: &dyn Render, _alpha: f32) {
+            renderer.clear(&Rect::new_from_x_y(0, 0, 600, 600));
+        }
+    }
+
+    #[test]
+    fn headless_game_loop_steps_fixed_ticks_with_scripted_input() {
+        let mut game_loop = HeadlessGameLoop::new(CountingGame::default());
+
+        game_loop.run(10, &KeyState::new());
+        game_loop.run_scripted(20, |tick| {
+            if tick % 2 == 0 {
+                KeyState::with_pressed(&["ArrowRight"])
+            } else {
+                KeyState::new()
+            }
+        });
+
+        assert_eq!(game_loop.ticks(), 30);
+        assert_eq!(game_loop.game().updates, 30);
+        assert_eq!(game_loop.game().right_presses, 10);
+        assert_eq!(
+            game_loop.elapsed(),
+            30.0 * GameLoopConfig::default().frame_size()
+        );
+    }
+
+    fn game_loop(config: GameLoopConfig) -> GameLoop {
+        GameLoop {
+            config,
+            last_frame: 0.0,
+            accumulated_delta: 0.0,
+            hidden: false,
+        }
+    }
+
+    #[test]
+    fn game_loop_caps_updates_and_reports_dropped_time() {
+        let mut game_loop = game_loop(GameLoopConfig {
+            tick_rate: 100.0,
+            max_updates_per_frame: 5,
+            max_accumulated_delta: 200.0,
+            ..GameLoopConfig::default()
+        });
+
+        assert_eq!(
+            game_loop.advance(25.0),
+            FrameUpdates {
+                updates: 2,
+                dropped: 0.0
+            }
+        );
+
+        // 5ms carried over plus a 1s stall: 805ms over the cap, then 150ms past the fifth update
+        assert_eq!(
+            game_loop.advance(1025.0),
+            FrameUpdates {
+                updates: 5,
+                dropped: 955.0
+            }
+        );
+        assert_eq!(game_loop.accumulated_delta, 0.0);
+    }
+
+    type SceneLog = Rc<RefCell<Vec<String>>>;
+
+    // Records every hook it gets, and asks for `change` on its next update.
+    struct LoggingScene {
+        name: &'static str,
+        log: SceneLog,
+        change: Option<SceneChange>,
+    }
+
+    impl LoggingScene {
+        fn new(name: &'static str, log: &SceneLog, change: Option<SceneChange>) -> Box<Self> {
+            Box::new(LoggingScene {
+                name,
+                log: log.clone(),
+                change,
+            })
+        }
+
+        fn record(&self, hook: &str) {
+            self.log
+                .borrow_mut()
+                .push(format!("{} {}", self.name, hook));
+        }
+    }
+
+    impl Scene for LoggingScene {
+        fn enter(&mut self) {
+            self.record("enter");
+        }
+
+        fn exit(&mut self) {
+            self.record("exit");
+        }
+
+        fn update(&mut self, _keystate: &KeyState) -> SceneChange {
+            self.record("update");
+            self.change.take().unwrap_or(SceneChange::Stay)
+        }
+
+        fn draw(&self, _renderer: &dyn Render, _alpha: f32) {
+            self.record("draw");
+        }
+    }
+
+    fn take(log: &SceneLog) -> Vec<String> {
+        log.borrow_mut().drain(..).collect()
+    }
+
+    #[test]
+    fn scene_stack_updates_the_top_scene_and_draws_them_all() {
+        let log = SceneLog::default();
+        let menu = LoggingScene::new("menu", &log, None);
+        let mut stack = SceneStack::new(LoggingScene::new(
+            "game",
+            &log,
+            Some(SceneChange::Push(menu)),
+        ));
+        let keystate = KeyState::new();
+
+        stack.update(&keystate);
+        assert_eq!(take(&log), ["game enter", "game update", "menu enter"]);
+
+        stack.update(&keystate);
+        stack.draw(&HeadlessRenderer::new(), 1.0);
+        assert_eq!(take(&log), ["menu update", "game draw", "menu draw"]);
+    }
+
+    #[test]
+    fn scene_stack_pops_and_replaces_with_exit_and_enter_hooks() {
+        let log = SceneLog::default();
+        let keystate = KeyState::new();
+        let mut stack = SceneStack::new(LoggingScene::new("game", &log, None));
+        stack.push(LoggingScene::new("menu", &log, Some(SceneChange::Pop)));
+        take(&log);
+
+        stack.update(&keystate);
+        assert_eq!(take(&log), ["menu update", "menu exit"]);
+
+        stack.replace(LoggingScene::new("title", &log, Some(SceneChange::Pop)));
+        assert_eq!(take(&log), ["game exit", "title enter"]);
+
+        stack.update(&keystate);
+        assert_eq!(take(&log), ["title update", "title exit"]);
+        assert!(stack.scenes.is_empty());
+    }
+
+    #[test]
+    fn the_pause_scene_holds_the_game_and_its_audio_until_a_resume_key() {
+        let log = SceneLog::default();
+        let audio = Rc::new(HeadlessAudio::new());
+        let screen = Rect::new_from_x_y(0, 0, 600, 600);
+        let mut stack = SceneStack::new(LoggingScene::new("game", &log, None));
+        stack.push(Box::new(PauseScene::new(
+            screen,
+            vec!["Escape".to_string()],
+            audio.clone(),
+        )));
+        take(&log);
+        assert!(audio.is_suspended());
+
+        stack.update(&KeyState::with_pressed(&["ArrowRight"]));
+        let renderer = HeadlessRenderer::new();
+        stack.draw(&renderer, 0.5);
+        assert_eq!(take(&log), ["game draw"]);
+        assert!(renderer.calls().contains(&DrawCall::FillRect {
+            rect: screen,
+            color: PAUSE_OVERLAY_COLOR.to_string(),
+        }));
+
+        stack.update(&KeyState::with_pressed(&["Escape"]));
+        assert!(!audio.is_suspended());
+        stack.update(&KeyState::new());
+        assert_eq!(take(&log), ["game update"]);
+    }
+
+    #[test]
+    fn key_state_reports_transitions_until_the_tick_ends() {
+        let mut keystate = KeyState::new();
+
+        keystate.set_pressed("ArrowUp");
+        // key repeat sends another keydown for a held key
+        keystate.set_pressed("ArrowUp");
+        assert!(keystate.is_pressed("ArrowUp"));
+        assert!(keystate.just_pressed("ArrowUp"));
+
+        keystate.end_tick();
+        keystate.set_pressed("ArrowUp");
+        assert!(keystate.is_pressed("ArrowUp"));
+        assert!(!keystate.just_pressed("ArrowUp"));
+
+        keystate.set_released("ArrowUp");
+        assert!(!keystate.is_pressed("ArrowUp"));
+        assert!(keystate.just_released("ArrowUp"));
+
+        keystate.end_tick();
+        assert!(!keystate.just_released("ArrowUp"));
+    }
+
+    #[derive(Default)]
+    struct JumpCounter {
+        jumps: Rc<std::cell::Cell<u32>>,
+    }
+
+    impl Scene for JumpCounter {
+        fn update(&mut self, keystate: &KeyState) -> SceneChange {
+            if keystate.just_pressed("ArrowUp") {
+                self.jumps.set(self.jumps.get() + 1);
+            }
+            SceneChange::Stay
+        }
+
+        fn draw(&self, _renderer: &dyn Render, _alpha: f32) {}
+    }
+
+    #[test]
+    fn a_press_is_seen_by_one_update_however_many_run_in_a_frame() {
+        let counter = JumpCounter::default();
+        let jumps = counter.jumps.clone();
+        let mut scenes = SceneStack::new(Box::new(counter));
+        let mut keystate = KeyState::new();
+
+        keystate.set_pressed("ArrowUp");
+        update_scenes(&mut scenes, &mut keystate, 3);
+        assert_eq!(jumps.get(), 1);
+
+        // tapped between two ticks, in a frame too short for an update
+        keystate.set_released("ArrowUp");
+        keystate.set_pressed("ArrowUp");
+        keystate.set_released("ArrowUp");
+        update_scenes(&mut scenes, &mut keystate, 0);
+        update_scenes(&mut scenes, &mut keystate, 2);
+        assert_eq!(jumps.get(), 2);
+    }
+
+    fn pad(buttons: &[bool], axes: &[f64]) -> GamepadState {
+        GamepadState {
+            buttons: buttons.to_vec(),
+            axes: axes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn gamepad_buttons_and_sticks_press_and_release_keys() {
+        let pads = HeadlessGamepads::new();
+        let mut gamepad = GamepadInput::new(pads.clone(), 0.25);
+        let mut keystate = KeyState::new();
+
+        pads.set(vec![pad(&[true, false], &[0.1, 0.8])]);
+        assert_eq!(
+            gamepad.update(&mut keystate),
+            vec!["GamepadAxis1+".to_string(), "GamepadButton0".to_string()]
+        );
+        assert!(keystate.just_pressed("GamepadButton0"));
+        assert!(keystate.is_pressed("GamepadAxis1+"));
+        // inside the deadzone
+        assert!(!keystate.is_pressed("GamepadAxis0+"));
+
+        keystate.end_tick();
+        pads.set(vec![pad(&[true, false], &[-0.5, 0.0])]);
+        assert_eq!(
+            gamepad.update(&mut keystate),
+            vec!["GamepadAxis0-".to_string()]
+        );
+        assert!(!keystate.just_pressed("GamepadButton0"));
+        assert!(keystate.just_released("GamepadAxis1+"));
+
+        pads.set(vec![]);
+        gamepad.update(&mut keystate);
+        assert!(!keystate.is_pressed("GamepadButton0"));
+        assert!(!keystate.is_pressed("GamepadAxis0-"));
+    }
+
+    #[test]
+    fn gamepad_input_leaves_keyboard_keys_alone() {
+        let pads = HeadlessGamepads::new();
+        let mut gamepad = GamepadInput::new(pads.clone(), 0.25);
+        let mut keystate = KeyState::with_pressed(&["ArrowRight"]);
+
+        pads.set(vec![pad(&[true], &[])]);
+        gamepad.update(&mut keystate);
+        pads.set(vec![pad(&[false], &[])]);
+        gamepad.update(&mut keystate);
+
+        assert!(keystate.is_pressed("ArrowRight"));
+        assert!(!keystate.is_pressed("GamepadButton0"));
+    }
+
+    fn pointer(phase: PointerPhase, id: i32, x: f64, y: f64, time: f64) -> PointerInput {
+        PointerInput {
+            phase,
+            id,
+            x,
+            y,
+            time,
+        }
+    }
+
+    fn gestures(inputs: &[PointerInput]) -> Vec<Gesture> {
+        let mut recognizer = GestureRecognizer::new();
+        inputs
+            .iter()
+            .filter_map(|input| recognizer.handle(input))
+            .collect()
+    }
+
+    #[test]
+    fn a_short_press_that_stays_put_is_a_tap() {
+        use PointerPhase::*;
+
+        assert_eq!(
+            gestures(&[
+                pointer(Down, 1, 100.0, 100.0, 0.0),
+                pointer(Move, 1, 105.0, 98.0, 50.0),
+                pointer(Up, 1, 108.0, 96.0, 120.0),
+            ]),
+            vec![Gesture::Tap]
+        );
+        // held too long
+        assert_eq!(
+            gestures(&[
+                pointer(Down, 1, 100.0, 100.0, 0.0),
+                pointer(Up, 1, 100.0, 100.0, 800.0),
+            ]),
+            vec![]
+        );
+        // cancelled by the browser, e.g. for a scroll
+        assert_eq!(
+            gestures(&[
+                pointer(Down, 1, 100.0, 100.0, 0.0),
+                pointer(Cancel, 1, 100.0, 100.0, 10.0),
+                pointer(Up, 1, 100.0, 100.0, 20.0),
+            ]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn dragging_down_is_a_single_swipe_reported_before_the_pointer_lifts() {
+        use PointerPhase::*;
+
+        assert_eq!(
+            gestures(&[
+                pointer(Down, 1, 100.0, 100.0, 0.0),
+                pointer(Move, 1, 102.0, 130.0, 30.0),
+                pointer(Move, 1, 104.0, 170.0, 60.0),
+                pointer(Move, 1, 104.0, 220.0, 90.0),
+                pointer(Up, 1, 104.0, 220.0, 100.0),
+            ]),
+            vec![Gesture::SwipeDown]
+        );
+        // a flick with no moves in between
+        assert_eq!(
+            gestures(&[
+                pointer(Down, 1, 100.0, 100.0, 0.0),
+                pointer(Up, 1, 100.0, 200.0, 80.0),
+            ]),
+            vec![Gesture::SwipeDown]
+        );
+        // sideways is neither
+        assert_eq!(
+            gestures(&[
+                pointer(Down, 1, 100.0, 100.0, 0.0),
+                pointer(Up, 1, 250.0, 160.0, 80.0),
+            ]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn a_pointer_going_down_takes_over_from_the_one_followed() {
+        use PointerPhase::*;
+
+        assert_eq!(
+            gestures(&[
+                pointer(Down, 1, 100.0, 100.0, 0.0),
+                pointer(Down, 2, 300.0, 100.0, 10.0),
+                pointer(Up, 2, 300.0, 100.0, 40.0),
+                pointer(Up, 1, 100.0, 100.0, 60.0),
+            ]),
+            vec![Gesture::Tap]
+        );
+        // the first press was released somewhere its Up never reached the canvas
+        assert_eq!(
+            gestures(&[
+                pointer(Down, 1, 100.0, 100.0, 0.0),
+                pointer(Down, 1, 200.0, 150.0, 2000.0),
+                pointer(Up, 1, 202.0, 150.0, 2100.0),
+            ]),
+            vec![Gesture::Tap]
+        );
+    }
+
+    #[test]
+    fn input_recording_stores_repeated_ticks_once_and_plays_them_back() {
+        let mut recording = InputRecording::new(42);
+        let mut keystate = KeyState::new();
+        let mut recorded = vec![];
+
+        for tick in 0..120 {
+            if tick == 10 {
+                keystate.set_pressed("ArrowRight");
+            }
+            if tick == 100 {
+                keystate.set_released("ArrowRight");
+            }
+            recording.record(&keystate);
+            recorded.push(keystate.clone());
+            keystate.end_tick();
+        }
+
+        // nothing, just pressed, held, just released, nothing
+        assert_eq!(recording.runs.len(), 5);
+        assert_eq!(recording.keystates().count(), 120);
+
+        let file = serde_json::to_string(&recording).unwrap();
+        let loaded: InputRecording = serde_json::from_str(&file).unwrap();
+        assert_eq!(loaded.seed(), 42);
+        assert_eq!(loaded.keystates().cloned().collect::<Vec<_>>(), recorded);
+
+        let mut game_loop = HeadlessGameLoop::new(CountingGame::default());
+        game_loop.replay(&loaded);
+        assert_eq!(game_loop.ticks(), 120);
+        assert_eq!(game_loop.game().right_presses, 90);
+    }
+
+    #[test]
+    fn mixer_levels_are_clamped_and_muting_keeps_the_gain() {
+        let mut mix = MixerSettings::default();
+        mix.set_gain(Bus::Master, 0.5);
+        mix.set_gain(Bus::Music, 0.4);
+        mix.set_gain(Bus::Sfx, 3.0);
+
+        assert_eq!(mix.bus(Bus::Master).volume(), 0.5);
+        assert_eq!(mix.bus(Bus::Music).volume(), 0.4);
+        assert_eq!(mix.bus(Bus::Sfx).volume(), 1.0);
+
+        mix.set_muted(Bus::Music, true);
+        assert_eq!(mix.bus(Bus::Music).volume(), 0.0);
+        assert_eq!(mix.bus(Bus::Sfx).volume(), 1.0);
+
+        mix.set_muted(Bus::Music, false);
+        assert_eq!(mix.bus(Bus::Music).volume(), 0.4);
+    }
+
+    #[test]
+    fn stored_mixer_levels_are_clamped() {
+        let stored: MixerSettings = serde_json::from_str(
+            r#"{
+                "master": { "gain": 5.0, "muted": false },
+                "music": { "gain": -1.0, "muted": true },
+                "sfx": { "gain": 0.5, "muted": false }
+            }"#,
+        )
+        .unwrap();
+
+        let mix = stored.clamped();
+
+        assert_eq!(mix.master.gain, 1.0);
+        assert_eq!(mix.music.gain, 0.0);
+        assert!(mix.music.muted);
+        assert_eq!(mix.sfx.gain, 0.5);
+    }
+
+    #[test]
+    fn headless_playbacks_follow_their_handles() {
+        let audio = HeadlessAudio::new();
+        let music = audio
+            .play_looping_sound(&SoundHandle::new(1), Bus::Music)
+            .unwrap();
+        let jump = audio.play_sound(&SoundHandle::new(0), Bus::Sfx).unwrap();
+
+        music.pause().unwrap();
+        music.set_volume(0.5).unwrap();
+        jump.stop().unwrap();
+        // a stopped sound doesn't come back
+        jump.resume().unwrap();
+
+        let playbacks = audio.playbacks();
+        assert_eq!(playbacks[0].status, PlaybackStatus::Paused);
+        assert_eq!(playbacks[0].volume, 0.5);
+        assert_eq!(playbacks[1].status, PlaybackStatus::Stopped);
+        assert!(!music.has_ended());
+        assert!(jump.has_ended());
+
+        music.resume().unwrap();
+        assert_eq!(audio.playbacks()[0].status, PlaybackStatus::Playing);
+    }
+
+    #[test]
+    fn sprites_have_to_be_on_their_file() {
+        let bounds = |offset, duration| AudioSpriteBounds { offset, duration };
+
+        assert_eq!(
+            bounds(1.5, Some(0.25)).sprite(2.0).unwrap(),
+            AudioSprite {
+                offset: 1.5,
+                duration: 0.25
+            }
+        );
+        assert_eq!(bounds(1.5, None).sprite(2.0).unwrap().duration, 0.5);
+        assert!(bounds(-0.5, Some(0.25)).sprite(2.0).is_err());
+        assert!(bounds(1.5, Some(0.0)).sprite(2.0).is_err());
+        assert!(bounds(1.5, Some(-0.25)).sprite(2.0).is_err());
+        assert!(bounds(1.5, Some(0.75)).sprite(2.0).is_err());
+        assert!(bounds(2.0, None).sprite(2.0).is_err());
+    }
+
+    #[test]
+    fn plays_wait_for_the_unlock_unless_they_were_stopped() {
+        let audio = HeadlessAudio::new();
+        audio.set_locked(true);
+        let music = audio
+            .play_looping_sound(&SoundHandle::new(1), Bus::Music)
+            .unwrap();
+        let jump = audio.play_sound(&SoundHandle::new(0), Bus::Sfx).unwrap();
+        jump.stop().unwrap();
+        music.pause().unwrap();
+        assert_eq!(audio.playbacks()[0].status, PlaybackStatus::Queued);
+        assert!(!music.has_ended());
+
+        audio.set_locked(false);
+
+        let playbacks = audio.playbacks();
+        assert_eq!(playbacks[0].status, PlaybackStatus::Playing);
+        assert_eq!(playbacks[1].status, PlaybackStatus::Stopped);
+    }
 }
@@ -1,38 +1,74 @@
 use crate::{
     browser,
-    engine::{self, Cell, Game, Image, KeyState, Point, Rect, Renderer, Sheet, SpriteSheet},
+    engine::{
+        self, AudioState, Bus, Cell, Fade, Game, GameLoopConfig, Image, ImageHandle,
+        InputRecording, KeyState, MixerSettings, PauseScene, PlaybackHandle, Point, Rect, Render,
+        Scene, SceneChange, Sheet, SoundHandle, SoundPlayer, SpriteSheet, TextAlign, TextStyle,
+    },
+    input::{Action, ActionMap},
+    reachability::{is_reachable, CollisionShape},
+    score::{HighScores, Score, ScoreEvent},
     segments::*,
-    sound::{Audio, Sound},
+    sfx::{SoundManager, SoundManifest},
+    sound::Audio,
     state::red_hat_boy_states::*,
-    state::{Event, RedHatBoyStateMachine},
+    state::{Event, GameEvent, RedHatBoyStateMachine},
+    storage::{self, LocalStorage, MemoryStorage, Storage},
 };
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use gloo_utils::format::JsValueSerdeExt;
 use rand::prelude::*;
-use std::rc::Rc;
-use web_sys::HtmlImageElement;
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 pub const HEIGHT: i16 = 600;
+pub const WIDTH: i16 = 600;
 pub const TIMELINE_MINIMUM: i16 = 1000;
 pub const OBSTACLE_BUFFER: i16 = 20;
+const SEGMENT_ATTEMPTS: usize = 5;
+const MUSIC_FADE_OUT_SECONDS: f64 = 2.0;
+const MUSIC_FADE_IN_SECONDS: f64 = 0.5;
+
+const HUD_FONT: &str = "24px sans-serif";
+const HUD_COLOR: &str = "#333";
+const HUD_MARGIN: i16 = 10;
+const HUD_BASELINE: i16 = 30;
+const UNLOCK_AUDIO_HINT: &str = "Press a key or tap for sound";
+const GAME_OVER_FONT: &str = "48px sans-serif";
+const HIGH_SCORE_LINE_HEIGHT: i16 = 28;
+const HINT_LINE_HEIGHT: i16 = 24;
+const SETTINGS_BACKGROUND: &str = "rgba(255, 255, 255, 0.9)";
+const LEVEL_STEP: f32 = 0.1;
 
 #[derive(Clone)]
 pub struct RedHatBoy {
     state_machine: RedHatBoyStateMachine,
     sprite_sheet: Sheet,
-    image: HtmlImageElement,
+    image: ImageHandle,
+    previous_position: Point,
 }
 
 impl RedHatBoy {
-    fn new(sprite_sheet: Sheet, image: HtmlImageElement, audio: Audio, sound: Sound) -> Self {
+    pub fn new(sprite_sheet: Sheet, image: ImageHandle) -> Self {
+        let state_machine = RedHatBoyStateMachine::Idle(RedHatBoyState::new());
+
         RedHatBoy {
-            state_machine: RedHatBoyStateMachine::Idle(RedHatBoyState::new(audio, sound)),
+            previous_position: state_machine.context().position,
+            state_machine,
             sprite_sheet,
             image,
         }
     }
 
+    fn reset(boy: Self) -> Self {
+        RedHatBoy::new(boy.sprite_sheet, boy.image)
+    }
+
+    fn knocked_out(&self) -> bool {
+        self.state_machine.knocked_out()
+    }
+
     fn run_right(&mut self) {
         self.state_machine = self.state_machine.clone().transition(Event::Run);
     }
@@ -49,8 +85,19 @@ impl RedHatBoy {
         self.state_machine = self.state_machine.clone().update();
     }
 
-    fn draw(&self, renderer: &Renderer) {
+    fn take_events(&mut self) -> Vec<GameEvent> {
+        self.state_machine.take_events()
+    }
+
+    fn save_position(&mut self) {
+        self.previous_position = self.state_machine.context().position;
+    }
+
+    fn draw(&self, renderer: &dyn Render, alpha: f32) {
         let sprite = self.current_sprite().expect("No sprite found");
+        let position = self.state_machine.context().position;
+        let drawn_at = position.interpolate(&self.previous_position, alpha);
+        let (offset_x, offset_y) = (drawn_at.x - position.x, drawn_at.y - position.y);
 
         renderer.draw_image(
             &self.image,
@@ -59,11 +106,11 @@ impl RedHatBoy {
                 sprite.frame.w.into(),
                 sprite.frame.h.into(),
             ),
-            &self.bounding_box(),
+            &self.bounding_box().offset_by(offset_x, offset_y),
         );
 
         // debug draw
-        renderer.draw_stroke_rect(&self.collision_box());
+        renderer.draw_stroke_rect(&self.collision_box().offset_by(offset_x, offset_y));
     }
 
     fn frame_name(&self) -> String {
@@ -105,6 +152,18 @@ impl RedHatBoy {
         )
     }
 
+    // The collision box is a little narrower on some frames than others, so segments are
+    // judged against the first running frame's whatever the boy is doing at the time.
+    fn running_collision_width(&self) -> i16 {
+        let sprite = self
+            .sprite_sheet
+            .frames
+            .get(&format!("{} (1).png", RUN_FRAME_NAME))
+            .expect("No running sprite found");
+
+        sprite.frame.w - 30
+    }
+
     fn knock_out(&mut self) {
         self.state_machine = self.state_machine.clone().transition(Event::KnockOut);
     }
@@ -113,6 +172,11 @@ impl RedHatBoy {
         self.state_machine = self.state_machine.clone().transition(Event::Land(position));
     }
 
+    fn center_x(&self) -> i16 {
+        let bounding_box = self.bounding_box();
+        bounding_box.x() + bounding_box.width / 2
+    }
+
     fn pos_y(&self) -> i16 {
         self.state_machine.context().position.y
     }
@@ -126,48 +190,872 @@ impl RedHatBoy {
     }
 }
 
-pub enum WalkTheDog {
-    Loading,
-    Loaded(Walk),
+pub struct WalkTheDog {
+    machine: Option<WalkTheDogStateMachine>,
+    seed: Option<u64>,
+    replay: Option<RunRecording>,
+    // What's left of the replay, tick by tick, while it's running
+    replay_input: Option<std::vec::IntoIter<KeyState>>,
 }
 
 impl WalkTheDog {
-    pub fn new() -> Self {
-        WalkTheDog::Loading {}
+    // Without a seed every new game picks a random one.
+    pub fn new(seed: Option<u64>) -> Self {
+        WalkTheDog {
+            machine: None,
+            seed,
+            replay: None,
+            replay_input: None,
+        }
+    }
+
+    // Plays the run back in place of the player's input. Nothing is saved while it does.
+    pub fn replaying(run: RunRecording) -> Self {
+        WalkTheDog {
+            seed: Some(run.input.seed()),
+            replay: Some(run),
+            ..WalkTheDog::new(None)
+        }
+    }
+}
+
+const LAST_RUN_KEY: &str = "walk_the_dog.last_run";
+const MIXER_KEY: &str = "walk_the_dog.mixer";
+
+// The input of a whole run, from the start of the walk to the boy being knocked out,
+// with the bindings that input was read through.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunRecording {
+    input: InputRecording,
+    bindings: ActionMap,
+}
+
+impl RunRecording {
+    // `last` is the player's own last run, anything else a URL to fetch one from.
+    pub async fn load(source: &str) -> Result<Self> {
+        if source == "last" {
+            storage::load(&LocalStorage::new()?, LAST_RUN_KEY)?
+                .ok_or_else(|| anyhow!("No run has been recorded yet"))
+        } else {
+            Ok(browser::fetch_json(source).await?.into_serde()?)
+        }
+    }
+
+    fn input_per_tick(&self) -> std::vec::IntoIter<KeyState> {
+        self.input
+            .keystates()
+            .cloned()
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+enum WalkTheDogStateMachine {
+    Ready(WalkTheDogState<Ready>),
+    Walking(WalkTheDogState<Walking>),
+    GameOver(WalkTheDogState<GameOver>),
+}
+
+impl WalkTheDogStateMachine {
+    fn new(walk: Walk) -> Self {
+        WalkTheDogStateMachine::Ready(WalkTheDogState::new(walk))
+    }
+
+    fn update(mut self, keystate: &KeyState) -> Self {
+        self.walk_mut().save_positions();
+        self.walk_mut().recording.record(keystate);
+        if self
+            .walk()
+            .actions
+            .borrow()
+            .just_pressed(keystate, Action::Mute)
+        {
+            self.walk().toggle_mute();
+        }
+
+        match self {
+            WalkTheDogStateMachine::Ready(state) => state.update(keystate).into(),
+            WalkTheDogStateMachine::Walking(state) => state.update(keystate).into(),
+            WalkTheDogStateMachine::GameOver(state) => state.new_game().into(),
+        }
+    }
+
+    fn walk(&self) -> &Walk {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => &state.walk,
+            WalkTheDogStateMachine::Walking(state) => &state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &state.walk,
+        }
+    }
+
+    fn walk_mut(&mut self) -> &mut Walk {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => &mut state.walk,
+            WalkTheDogStateMachine::Walking(state) => &mut state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &mut state.walk,
+        }
+    }
+
+    fn draw(&self, renderer: &dyn Render, alpha: f32) {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => state.draw(renderer, alpha),
+            WalkTheDogStateMachine::Walking(state) => state.draw(renderer, alpha),
+            WalkTheDogStateMachine::GameOver(state) => state.draw(renderer, alpha),
+        }
+    }
+}
+
+struct WalkTheDogState<T> {
+    _state: T,
+    walk: Walk,
+}
+
+impl<T> WalkTheDogState<T> {
+    fn draw(&self, renderer: &dyn Render, alpha: f32) {
+        self.walk.draw(renderer, alpha);
+        self.walk.draw_hud(renderer);
+    }
+}
+
+struct Ready;
+
+enum ReadyEndState {
+    Complete(WalkTheDogState<Walking>),
+    Continue(WalkTheDogState<Ready>),
+}
+
+impl WalkTheDogState<Ready> {
+    fn new(walk: Walk) -> Self {
+        WalkTheDogState {
+            _state: Ready,
+            walk,
+        }
+    }
+
+    fn update(mut self, keystate: &KeyState) -> ReadyEndState {
+        self.walk.boy.update();
+
+        if self.walk.actions.borrow().triggered(keystate, Action::Run) {
+            ReadyEndState::Complete(self.start_running())
+        } else {
+            ReadyEndState::Continue(self)
+        }
+    }
+
+    fn start_running(mut self) -> WalkTheDogState<Walking> {
+        self.walk.boy.run_right();
+
+        WalkTheDogState {
+            _state: Walking,
+            walk: self.walk,
+        }
+    }
+}
+
+struct Walking;
+
+enum WalkingEndState {
+    Complete(WalkTheDogState<GameOver>),
+    Continue(WalkTheDogState<Walking>),
+}
+
+impl WalkTheDogState<Walking> {
+    fn update(mut self, keystate: &KeyState) -> WalkingEndState {
+        let walk = &mut self.walk;
+
+        if walk.actions.borrow().just_pressed(keystate, Action::Jump) {
+            walk.boy.jump();
+        }
+
+        if walk.actions.borrow().triggered(keystate, Action::Slide) {
+            walk.boy.slide();
+        }
+
+        let velocity = walk.velocity();
+
+        walk.boy.update();
+        walk.score.add_distance(velocity);
+
+        // remove all obstacles that are out of screen
+        walk.obstacles.retain(|obstacle| obstacle.right() > 0);
+
+        // !NOTE: This is a workaround for borrow checker.
+        // Expect future Rust update to fix this issue.
+        // ref: https://stackoverflow.com/questions/64921625/closure-requires-unique-access-to-self-but-it-is-already-borrowed
+        let mut obstacles = std::mem::take(&mut walk.obstacles);
+        obstacles.iter_mut().for_each(|obstacle| {
+            obstacle.move_horizontally(velocity);
+            if let Some(event) = obstacle.check_intersection(&mut walk.boy) {
+                walk.score.record(event);
+                walk.play_sound(GameEvent::Scored);
+            }
+        });
+        walk.obstacles = obstacles;
+        walk.play_boy_sounds();
+
+        let [bg1, bg2] = &mut walk.backgrounds;
+        bg1.move_horizontally(velocity);
+        bg2.move_horizontally(velocity);
+
+        if bg1.right() < 0 {
+            bg1.set_x(bg2.right());
+        }
+        if bg2.right() < 0 {
+            bg2.set_x(bg1.right());
+        }
+
+        if walk.timeline < TIMELINE_MINIMUM {
+            walk.generate_next_segment();
+        } else {
+            walk.timeline += velocity;
+        }
+
+        if walk.knocked_out() {
+            WalkingEndState::Complete(self.end_game())
+        } else {
+            WalkingEndState::Continue(self)
+        }
+    }
+
+    fn end_game(mut self) -> WalkTheDogState<GameOver> {
+        self.walk.record_high_score();
+        self.walk.save_run();
+        self.walk.fade_out_music();
+
+        WalkTheDogState {
+            _state: GameOver,
+            walk: self.walk,
+        }
+    }
+}
+
+// The knocked out walk stays under the game over screen, and starts over on the first update
+// after the player has left that screen.
+struct GameOver;
+
+impl WalkTheDogState<GameOver> {
+    fn new_game(self) -> WalkTheDogState<Ready> {
+        WalkTheDogState {
+            _state: Ready,
+            walk: Walk::reset(self.walk),
+        }
+    }
+}
+
+// The actions the game over screen asks the player for new keys for, in order.
+const REBINDABLE: [(Action, &str); 3] = [
+    (Action::Run, "run"),
+    (Action::Jump, "jump"),
+    (Action::Slide, "slide"),
+];
+
+// Pushed over the walk once the boy is knocked out, it pops when the player asks for a new
+// game. It swaps with a RebindScene while the player picks their own keys, those are shared
+// with the walk under it. Once the music has faded out it's paused, so the next walk picks
+// it up where it faded.
+#[derive(Clone)]
+struct GameOverScene {
+    actions: Rc<RefCell<ActionMap>>,
+    default_actions: ActionMap,
+    storage: Rc<dyn Storage>,
+    audio: Rc<dyn SoundPlayer>,
+    music: PlaybackHandle,
+    // Updates left until the music has faded out
+    fading: u32,
+    seed: u64,
+    high_scores: HighScores,
+}
+
+impl GameOverScene {
+    fn new(walk: &Walk) -> Self {
+        GameOverScene {
+            actions: walk.actions.clone(),
+            default_actions: walk.default_actions.clone(),
+            storage: walk.storage.clone(),
+            audio: walk.sounds.audio().clone(),
+            music: walk.sounds.music().clone(),
+            fading: (MUSIC_FADE_OUT_SECONDS * GameLoopConfig::default().tick_rate as f64).ceil()
+                as u32,
+            seed: walk.seed,
+            high_scores: walk.high_scores.clone(),
+        }
+    }
+
+    fn draw_results(&self, renderer: &dyn Render) {
+        renderer.draw_text(
+            "Game Over",
+            &Point::new(WIDTH / 2, HEIGHT / 2),
+            &TextStyle::new(GAME_OVER_FONT, HUD_COLOR, TextAlign::Center),
+        );
+
+        let style = TextStyle::new(HUD_FONT, HUD_COLOR, TextAlign::Center);
+        renderer.draw_text(
+            &format!("Seed: {}", self.seed),
+            &Point::new(WIDTH / 2, HEIGHT / 2 - 60),
+            &style,
+        );
+
+        let mut y = HEIGHT / 2 + 120;
+        renderer.draw_text("High Scores", &Point::new(WIDTH / 2, y), &style);
+        for (rank, entry) in self.high_scores.entries().iter().enumerate() {
+            y += HIGH_SCORE_LINE_HEIGHT;
+            renderer.draw_text(
+                &format!("{}. {} ({} m)", rank + 1, entry.score, entry.distance),
+                &Point::new(WIDTH / 2, y),
+                &style,
+            );
+        }
+    }
+}
+
+impl Scene for GameOverScene {
+    fn update(&mut self, keystate: &KeyState) -> SceneChange {
+        if self.fading > 0 {
+            self.fading -= 1;
+            if self.fading == 0 {
+                if let Err(err) = self.music.pause() {
+                    log!("Error pausing the music: {}", err);
+                }
+            }
+        }
+
+        let actions = self.actions.borrow();
+        if actions.triggered(keystate, Action::NewGame) {
+            SceneChange::Pop
+        } else if actions.just_pressed(keystate, Action::Pause) {
+            SceneChange::Push(pause_scene(&actions, self.audio.clone()))
+        } else if actions.just_pressed(keystate, Action::Rebind) {
+            SceneChange::Replace(Box::new(RebindScene {
+                game_over: self.clone(),
+                rebinding: 0,
+            }))
+        } else if actions.just_pressed(keystate, Action::Settings) {
+            SceneChange::Push(Box::new(SettingsScene {
+                actions: self.actions.clone(),
+                storage: self.storage.clone(),
+                audio: self.audio.clone(),
+                selected: 0,
+            }))
+        } else {
+            if actions.just_pressed(keystate, Action::Mute) {
+                toggle_mute(self.audio.as_ref(), self.storage.as_ref());
+            }
+            SceneChange::Stay
+        }
+    }
+
+    fn draw(&self, renderer: &dyn Render, _alpha: f32) {
+        self.draw_results(renderer);
+        let actions = self.actions.borrow();
+        draw_hints(
+            renderer,
+            &[
+                format!("Press {} for a new game", actions.describe(Action::NewGame)),
+                format!("Press {} to add keys", actions.describe(Action::Rebind)),
+                format!(
+                    "Press {} for sound settings",
+                    actions.describe(Action::Settings)
+                ),
+            ],
+        );
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        pause_audio(self.audio.as_ref(), paused);
+    }
+}
+
+// Asks for a key for each of REBINDABLE in turn, then swaps back to the game over screen.
+// Every key is offered as a binding here, so nothing pauses or mutes the game.
+struct RebindScene {
+    game_over: GameOverScene,
+    // Which of REBINDABLE is waiting for a key
+    rebinding: usize,
+}
+
+impl RebindScene {
+    // Binds the key to the action for this game and every one after it, unless it's taken.
+    fn rebind(&self, action: Action, code: &str) -> bool {
+        let game_over = &self.game_over;
+        if game_over.actions.borrow_mut().bind(action, code).is_err() {
+            return false;
+        }
+        if let Err(err) = game_over
+            .actions
+            .borrow()
+            .save(game_over.storage.as_ref(), &game_over.default_actions)
+        {
+            log!("Error saving key bindings: {}", err);
+        }
+        true
+    }
+}
+
+impl Scene for RebindScene {
+    fn update(&mut self, keystate: &KeyState) -> SceneChange {
+        // a key that's taken is ignored, the player is asked again
+        let action = REBINDABLE[self.rebinding].0;
+        if !keystate
+            .just_pressed_codes()
+            .any(|code| self.rebind(action, code))
+        {
+            return SceneChange::Stay;
+        }
+
+        self.rebinding += 1;
+        if self.rebinding < REBINDABLE.len() {
+            SceneChange::Stay
+        } else {
+            SceneChange::Replace(Box::new(self.game_over.clone()))
+        }
+    }
+
+    fn draw(&self, renderer: &dyn Render, _alpha: f32) {
+        self.game_over.draw_results(renderer);
+        draw_hints(
+            renderer,
+            &[format!(
+                "Press a free key or button to {}",
+                REBINDABLE[self.rebinding].1
+            )],
+        );
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.game_over.set_paused(paused);
+    }
+}
+
+// The buses the settings screen lists, in order.
+const MIXER_BUSES: [(Bus, &str); 3] = [
+    (Bus::Master, "Volume"),
+    (Bus::Music, "Music"),
+    (Bus::Sfx, "Sound effects"),
+];
+const SETTINGS_UP: [&str; 2] = ["ArrowUp", "GamepadButton12"];
+const SETTINGS_DOWN: [&str; 2] = ["ArrowDown", "GamepadButton13"];
+const SETTINGS_LOWER: [&str; 2] = ["ArrowLeft", "GamepadButton14"];
+const SETTINGS_RAISE: [&str; 2] = ["ArrowRight", "GamepadButton15"];
+
+// Pushed over the game over screen to balance the mixer. Up and down pick a bus, left and
+// right change its level and the Mute keys mute only that bus. Every change is heard at once
+// and kept, the Settings keys go back.
+struct SettingsScene {
+    actions: Rc<RefCell<ActionMap>>,
+    storage: Rc<dyn Storage>,
+    audio: Rc<dyn SoundPlayer>,
+    // Which of MIXER_BUSES the player is changing
+    selected: usize,
+}
+
+impl Scene for SettingsScene {
+    fn update(&mut self, keystate: &KeyState) -> SceneChange {
+        let pressed = |codes: &[&str]| codes.iter().any(|code| keystate.just_pressed(code));
+        let actions = self.actions.borrow();
+        if actions.just_pressed(keystate, Action::Settings) {
+            return SceneChange::Pop;
+        }
+
+        if pressed(&SETTINGS_UP) {
+            self.selected = (self.selected + MIXER_BUSES.len() - 1) % MIXER_BUSES.len();
+        }
+        if pressed(&SETTINGS_DOWN) {
+            self.selected = (self.selected + 1) % MIXER_BUSES.len();
+        }
+
+        let bus = MIXER_BUSES[self.selected].0;
+        let mut mix = self.audio.mix();
+        if pressed(&SETTINGS_LOWER) {
+            mix.set_gain(bus, mix.bus(bus).gain - LEVEL_STEP);
+        }
+        if pressed(&SETTINGS_RAISE) {
+            mix.set_gain(bus, mix.bus(bus).gain + LEVEL_STEP);
+        }
+        if actions.just_pressed(keystate, Action::Mute) {
+            mix.set_muted(bus, !mix.bus(bus).muted);
+        }
+        if mix != self.audio.mix() {
+            set_mix(self.audio.as_ref(), self.storage.as_ref(), &mix);
+        }
+        SceneChange::Stay
+    }
+
+    fn draw(&self, renderer: &dyn Render, _alpha: f32) {
+        renderer.fill_rect(
+            &Rect::new(Point::new(0, 0), WIDTH, HEIGHT),
+            SETTINGS_BACKGROUND,
+        );
+        renderer.draw_text(
+            "Sound",
+            &Point::new(WIDTH / 2, HEIGHT / 2 - 60),
+            &TextStyle::new(GAME_OVER_FONT, HUD_COLOR, TextAlign::Center),
+        );
+
+        let mix = self.audio.mix();
+        let style = TextStyle::new(HUD_FONT, HUD_COLOR, TextAlign::Center);
+        for (line, (bus, name)) in (0..).zip(MIXER_BUSES) {
+            let level = mix.bus(bus);
+            let level = if level.muted {
+                "muted".to_string()
+            } else {
+                format!("{}%", (level.gain * 100.0).round())
+            };
+            let marker = if line as usize == self.selected {
+                "> "
+            } else {
+                ""
+            };
+            renderer.draw_text(
+                &format!("{}{}: {}", marker, name, level),
+                &Point::new(WIDTH / 2, HEIGHT / 2 - 20 + line * HINT_LINE_HEIGHT),
+                &style,
+            );
+        }
+
+        let actions = self.actions.borrow();
+        draw_hints(
+            renderer,
+            &[
+                "Up and down pick, left and right change the level".to_string(),
+                format!(
+                    "Press {} to mute, {} to go back",
+                    actions.describe(Action::Mute),
+                    actions.describe(Action::Settings)
+                ),
+            ],
+        );
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        pause_audio(self.audio.as_ref(), paused);
+    }
+}
+
+fn draw_hints(renderer: &dyn Render, hints: &[String]) {
+    for (line, hint) in (0..).zip(hints) {
+        renderer.draw_text(
+            hint,
+            &Point::new(WIDTH / 2, HEIGHT / 2 + 40 + line * HINT_LINE_HEIGHT),
+            &TextStyle::new(HUD_FONT, HUD_COLOR, TextAlign::Center),
+        );
+    }
+}
+
+// The Pause keys resume the game as well.
+fn pause_scene(actions: &ActionMap, audio: Rc<dyn SoundPlayer>) -> Box<dyn Scene> {
+    Box::new(PauseScene::new(
+        Rect::new(Point::new(0, 0), WIDTH, HEIGHT),
+        actions.bindings(Action::Pause).to_vec(),
+        audio,
+    ))
+}
+
+fn pause_audio(audio: &dyn SoundPlayer, paused: bool) {
+    let result = if paused {
+        audio.suspend()
+    } else {
+        audio.resume()
+    };
+
+    if let Err(err) = result {
+        log!("Error pausing audio: {}", err);
+    }
+}
+
+// The new levels are heard at once and kept for next time.
+fn set_mix(audio: &dyn SoundPlayer, storage: &dyn Storage, mix: &MixerSettings) {
+    if let Err(err) = audio.set_mix(mix) {
+        log!("Error setting the mixer: {}", err);
+    }
+    if let Err(err) = storage::save(storage, MIXER_KEY, mix) {
+        log!("Error saving the mixer settings: {}", err);
+    }
+}
+
+fn toggle_mute(audio: &dyn SoundPlayer, storage: &dyn Storage) {
+    let mut mix = audio.mix();
+    mix.set_muted(Bus::Master, !mix.master.muted);
+    set_mix(audio, storage, &mix);
+}
+
+impl From<WalkTheDogState<Ready>> for WalkTheDogStateMachine {
+    fn from(state: WalkTheDogState<Ready>) -> Self {
+        WalkTheDogStateMachine::Ready(state)
+    }
+}
+
+impl From<WalkTheDogState<Walking>> for WalkTheDogStateMachine {
+    fn from(state: WalkTheDogState<Walking>) -> Self {
+        WalkTheDogStateMachine::Walking(state)
+    }
+}
+
+impl From<WalkTheDogState<GameOver>> for WalkTheDogStateMachine {
+    fn from(state: WalkTheDogState<GameOver>) -> Self {
+        WalkTheDogStateMachine::GameOver(state)
+    }
+}
+
+impl From<ReadyEndState> for WalkTheDogStateMachine {
+    fn from(state: ReadyEndState) -> Self {
+        match state {
+            ReadyEndState::Complete(walking) => walking.into(),
+            ReadyEndState::Continue(ready) => ready.into(),
+        }
+    }
+}
+
+impl From<WalkingEndState> for WalkTheDogStateMachine {
+    fn from(state: WalkingEndState) -> Self {
+        match state {
+            WalkingEndState::Complete(game_over) => game_over.into(),
+            WalkingEndState::Continue(walking) => walking.into(),
+        }
     }
 }
 
 pub struct Walk {
     boy: RedHatBoy,
     backgrounds: [Image; 2],
+    sounds: SoundManager,
     obstacles: Vec<Box<dyn Obstacle>>,
-    obstacle_sheet: Rc<SpriteSheet>,
-    stone: HtmlImageElement,
+    segments: Segments,
+    selector: SegmentSelector,
     timeline: i16,
+    score: Score,
+    high_scores: HighScores,
+    storage: Rc<dyn Storage>,
+    // Shared with the game over screen, where the player can change them
+    actions: Rc<RefCell<ActionMap>>,
+    // What `actions` are saved against, so only the player's changes are kept
+    default_actions: ActionMap,
+    requested_seed: Option<u64>,
+    seed: u64,
+    rng: StdRng,
+    recording: InputRecording,
 }
 
 impl Walk {
+    pub fn new(
+        boy: RedHatBoy,
+        background: ImageHandle,
+        sounds: SoundManager,
+        segments: Segments,
+        storage: Rc<dyn Storage>,
+        default_actions: ActionMap,
+        requested_seed: Option<u64>,
+    ) -> Self {
+        let starting_obstacles = segments.starting_segment(0);
+        let selector = segments.selector();
+        let timeline = rightmost(&starting_obstacles);
+        let high_scores = HighScores::load(storage.as_ref()).unwrap_or_else(|err| {
+            log!("Error loading high scores: {}", err);
+            HighScores::default()
+        });
+        let actions =
+            ActionMap::load(storage.as_ref(), default_actions.clone()).unwrap_or_else(|err| {
+                log!("Error loading key bindings: {}", err);
+                default_actions.clone()
+            });
+        let seed = requested_seed.unwrap_or_else(random);
+
+        Walk {
+            boy,
+            backgrounds: [
+                Image::new(background, Point { x: 0, y: 0 }),
+                Image::new(
+                    background,
+                    Point {
+                        x: background.width(),
+                        y: 0,
+                    },
+                ),
+            ],
+            sounds,
+            obstacles: starting_obstacles,
+            segments,
+            selector,
+            timeline,
+            score: Score::new(),
+            high_scores,
+            storage,
+            actions: Rc::new(RefCell::new(actions)),
+            default_actions,
+            requested_seed,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            recording: InputRecording::new(seed),
+        }
+    }
+
+    // Starts over with the assets that are already loaded, nothing is fetched again.
+    fn reset(walk: Self) -> Self {
+        let starting_obstacles = walk.segments.starting_segment(0);
+        let timeline = rightmost(&starting_obstacles);
+
+        let mut backgrounds = walk.backgrounds;
+        let [bg1, bg2] = &mut backgrounds;
+        bg1.set_x(0);
+        bg2.set_x(bg1.right());
+        let seed = walk.requested_seed.unwrap_or_else(random);
+        let music = walk.sounds.music();
+        if let Err(err) = music
+            .resume()
+            .and_then(|_| music.fade_to(1.0, MUSIC_FADE_IN_SECONDS, Fade::Linear))
+        {
+            log!("Error fading in the music: {}", err);
+        }
+
+        Walk {
+            boy: RedHatBoy::reset(walk.boy),
+            backgrounds,
+            sounds: walk.sounds,
+            obstacles: starting_obstacles,
+            selector: walk.segments.selector(),
+            segments: walk.segments,
+            timeline,
+            score: Score::new(),
+            high_scores: walk.high_scores,
+            storage: walk.storage,
+            actions: walk.actions,
+            default_actions: walk.default_actions,
+            requested_seed: walk.requested_seed,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            recording: InputRecording::new(seed),
+        }
+    }
+
     fn velocity(&self) -> i16 {
         -self.boy.walking_speed()
     }
 
-    fn generate_next_segment(&mut self) {
-        let mut rng = thread_rng();
-        let next_segment = rng.gen_range(0..2);
-
-        let mut next_obstacles = match next_segment {
-            0 => stone_and_platform(
-                self.stone.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            1 => platform_and_stone(
-                self.stone.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            _ => vec![],
+    fn knocked_out(&self) -> bool {
+        self.boy.knocked_out()
+    }
+
+    fn record_high_score(&mut self) {
+        if self.high_scores.insert(&self.score).is_some() {
+            if let Err(err) = self.high_scores.save(self.storage.as_ref()) {
+                log!("Error saving high scores: {}", err);
+            }
+        }
+    }
+
+    fn toggle_mute(&self) {
+        toggle_mute(self.sounds.audio().as_ref(), self.storage.as_ref());
+    }
+
+    // Nothing is heard once the game has stopped, not even the music.
+    fn shutdown(&self) {
+        if let Err(err) = self.sounds.music().stop() {
+            log!("Error stopping the music: {}", err);
+        }
+        if let Err(err) = self.sounds.audio().close() {
+            log!("Error closing the audio: {}", err);
+        }
+    }
+
+    fn fade_out_music(&self) {
+        if let Err(err) =
+            self.sounds
+                .music()
+                .fade_to(0.0, MUSIC_FADE_OUT_SECONDS, Fade::Exponential)
+        {
+            log!("Error fading out the music: {}", err);
+        }
+    }
+
+    fn play_sound(&mut self, event: GameEvent) {
+        if let Err(err) = self.sounds.play(event) {
+            log!("Error playing the sound for {:?}: {}", event, err);
+        }
+    }
+
+    fn play_boy_sounds(&mut self) {
+        for event in self.boy.take_events() {
+            self.play_sound(event);
+        }
+    }
+
+    fn save_run(&self) {
+        let run = RunRecording {
+            input: self.recording.clone(),
+            bindings: self.actions.borrow().clone(),
         };
+        if let Err(err) = storage::save(self.storage.as_ref(), LAST_RUN_KEY, &run) {
+            log!("Error saving the run: {}", err);
+        }
+    }
+
+    // Called before every update, everything is drawn between these and the updated positions.
+    fn save_positions(&mut self) {
+        self.boy.save_position();
+        self.backgrounds.iter_mut().for_each(Image::save_position);
+        self.obstacles
+            .iter_mut()
+            .for_each(|obstacle| obstacle.save_position());
+    }
+
+    fn draw(&self, renderer: &dyn Render, alpha: f32) {
+        self.backgrounds
+            .iter()
+            .for_each(|bg| bg.draw(renderer, alpha));
+        self.boy.draw(renderer, alpha);
+        self.obstacles.iter().for_each(|obstacle| {
+            obstacle.draw(renderer, alpha);
+        });
+    }
+
+    fn draw_hud(&self, renderer: &dyn Render) {
+        renderer.draw_text(
+            &format!("Score: {}", self.score.total()),
+            &Point::new(HUD_MARGIN, HUD_BASELINE),
+            &TextStyle::new(HUD_FONT, HUD_COLOR, TextAlign::Left),
+        );
+        renderer.draw_text(
+            &format!("{} m", self.score.distance()),
+            &Point::new(WIDTH - HUD_MARGIN, HUD_BASELINE),
+            &TextStyle::new(HUD_FONT, HUD_COLOR, TextAlign::Right),
+        );
+        // The browser holds the music back until the player interacts with the page.
+        if self.sounds.audio().state() == AudioState::Locked {
+            renderer.draw_text(
+                UNLOCK_AUDIO_HINT,
+                &Point::new(WIDTH / 2, HEIGHT - HUD_MARGIN),
+                &TextStyle::new(HUD_FONT, HUD_COLOR, TextAlign::Center),
+            );
+        }
+    }
+
+    // Tries a few picks from the catalogue before falling back to the starting segment,
+    // skipping any the boy couldn't get through.
+    fn generate_next_segment(&mut self) {
+        let offset_x = self.timeline + OBSTACLE_BUFFER;
+        let boy_width = self.boy.running_collision_width();
+
+        let mut next_obstacles = (0..SEGMENT_ATTEMPTS)
+            .find_map(|_| {
+                let next_segment =
+                    self.segments
+                        .select(&self.selector, self.score.distance(), &mut self.rng);
+                let obstacles = self.segments.create(next_segment, offset_x);
+
+                if is_reachable(&obstacles, boy_width) {
+                    self.selector.record(next_segment);
+                    Some(obstacles)
+                } else {
+                    log!(
+                        "Skipping unreachable segment {}",
+                        self.segments.name(next_segment)
+                    );
+                    None
+                }
+            })
+            .unwrap_or_else(|| {
+                self.selector.record(self.segments.starting_index());
+                self.segments.starting_segment(offset_x)
+            });
 
         self.timeline = rightmost(&next_obstacles);
         self.obstacles.append(&mut next_obstacles);
@@ -179,6 +1067,8 @@ pub struct Platform {
     bounding_boxes: Vec<Rect>,
     sprites: Vec<Cell>,
     position: Point,
+    previous_position: Point,
+    landed_on: bool,
 }
 
 impl Platform {
@@ -207,8 +1097,10 @@ impl Platform {
         Platform {
             sheet,
             position,
+            previous_position: position,
             sprites,
             bounding_boxes,
+            landed_on: false,
         }
     }
 
@@ -220,20 +1112,38 @@ impl Platform {
 const SPRITE_PATH: &str = "walk_the_dog_assets-0.0.7/sprite_sheets/";
 const BG_PATH: &str = "walk_the_dog_assets-0.0.7/resized/freetileset/png/BG/";
 const OBJECT_PATH: &str = "walk_the_dog_assets-0.0.7/resized/freetileset/png/Object/";
+const SEGMENTS_PATH: &str = "segments.json";
+const BINDINGS_PATH: &str = "bindings.json";
+const SOUNDS_PATH: &str = "sounds.json";
+
+// The sprite sheet is fetched once, whatever number of sounds are on it.
+async fn load_sound_effects(
+    audio: &Audio,
+    manifest: &SoundManifest,
+) -> Result<HashMap<String, SoundHandle>> {
+    let sprites = match &manifest.sprite_sheet {
+        Some(sheet) => audio.load_sprite_sheet(sheet).await?,
+        None => HashMap::new(),
+    };
+
+    let mut files = HashMap::new();
+    for file in manifest.files() {
+        files.insert(file.to_string(), audio.load_sound(file).await?);
+    }
+    manifest.resolve(&files, &sprites)
+}
 
 #[async_trait(?Send)]
 impl Game for WalkTheDog {
-    async fn initialize(&self) -> Result<Box<dyn Game>> {
-        match self {
-            WalkTheDog::Loading => {
+    async fn initialize(&self) -> Result<Box<dyn Scene>> {
+        match self.machine {
+            None => {
                 let sheet: Sheet =
                     browser::fetch_json((String::from(SPRITE_PATH) + "rhb_trimmed.json").as_str())
                         .await?
                         .into_serde()?;
                 let background =
                     engine::load_image((String::from(BG_PATH) + "BG.png").as_str()).await?;
-                let stone_image =
-                    engine::load_image((String::from(OBJECT_PATH) + "Stone.png").as_str()).await?;
                 let tiles =
                     browser::fetch_json((String::from(SPRITE_PATH) + "tiles.json").as_str())
                         .await?;
@@ -241,125 +1151,145 @@ impl Game for WalkTheDog {
                     engine::load_image((String::from(SPRITE_PATH) + "tiles.png").as_str()).await?,
                     tiles.into_serde::<Sheet>()?,
                 ));
-
+                let catalogue: SegmentCatalogue =
+                    browser::fetch_json(SEGMENTS_PATH).await?.into_serde()?;
+                let mut barrier_images = HashMap::new();
+                for name in catalogue.barrier_images() {
+                    let image =
+                        engine::load_image((String::from(OBJECT_PATH) + &name).as_str()).await?;
+                    barrier_images.insert(name, image);
+                }
+                let segments = Segments::new(catalogue, barrier_images, sprite_sheet)?;
+                let default_actions: ActionMap =
+                    browser::fetch_json(BINDINGS_PATH).await?.into_serde()?;
+
+                let local_storage = LocalStorage::new();
+                // a replay keeps nothing, but it's heard at the player's own levels
+                let mix = match &local_storage {
+                    Ok(storage) => storage::load(storage, MIXER_KEY).unwrap_or_else(|err| {
+                        log!("Error loading the mixer settings: {}", err);
+                        None
+                    }),
+                    Err(_) => None,
+                };
+                let storage: Rc<dyn Storage> = match (&self.replay, local_storage) {
+                    (Some(_), _) => Rc::new(MemoryStorage::new()),
+                    (None, Ok(storage)) => Rc::new(storage),
+                    (None, Err(err)) => {
+                        log!("High scores will not be kept: {}", err);
+                        Rc::new(MemoryStorage::new())
+                    }
+                };
                 let audio = Audio::new()?;
-                let sound = audio
-                    .load_sound("walk_the_dog_assets-0.0.7/sounds/SFX_Jump_23.mp3")
-                    .await?;
+                audio.set_mix(&mix.map_or_else(MixerSettings::default, MixerSettings::clamped))?;
+                let manifest: SoundManifest =
+                    browser::fetch_json(SOUNDS_PATH).await?.into_serde()?;
+                let sound_effects = load_sound_effects(&audio, &manifest).await?;
                 let background_music = audio
                     .load_sound("walk_the_dog_assets-0.0.7/sounds/background_song.mp3")
                     .await?;
 
-                audio.play_looping_sound(&background_music, 0.01)?;
+                let music = audio.play_looping_sound(&background_music, Bus::Music)?;
+                let sounds = SoundManager::new(Rc::new(audio), music, manifest, sound_effects)?;
 
                 let rhb = RedHatBoy::new(
                     sheet,
                     engine::load_image((String::from(SPRITE_PATH) + "rhb_trimmed.png").as_str())
                         .await?,
-                    audio,
-                    sound,
                 );
 
-                let starting_obstacles =
-                    stone_and_platform(stone_image.clone(), sprite_sheet.clone(), 0);
-                let timeline = rightmost(&starting_obstacles);
-
-                let background_width = background.width();
-
-                Ok(Box::new(WalkTheDog::Loaded(Walk {
-                    boy: rhb,
-                    backgrounds: [
-                        Image::new(background.clone(), Point { x: 0, y: 0 }),
-                        Image::new(
-                            background,
-                            Point {
-                                x: background_width as i16,
-                                y: 0,
-                            },
-                        ),
-                    ],
-                    obstacles: starting_obstacles,
-                    obstacle_sheet: sprite_sheet,
-                    stone: stone_image,
-                    timeline,
-                })))
+                let default_actions = match &self.replay {
+                    Some(run) => run.bindings.clone(),
+                    None => default_actions,
+                };
+
+                let machine = WalkTheDogStateMachine::new(Walk::new(
+                    rhb,
+                    background,
+                    sounds,
+                    segments,
+                    storage,
+                    default_actions,
+                    self.seed,
+                ));
+
+                Ok(Box::new(WalkTheDog {
+                    machine: Some(machine),
+                    seed: self.seed,
+                    replay: None,
+                    replay_input: self.replay.as_ref().map(RunRecording::input_per_tick),
+                }))
             }
-            WalkTheDog::Loaded(_) => Err(anyhow!("Error: Game is already initialized")),
+            Some(_) => Err(anyhow!("Error: Game is already initialized")),
         }
     }
+}
 
-    fn update(&mut self, keystate: &KeyState) {
-        if let WalkTheDog::Loaded(walk) = self {
-            if keystate.is_pressed("ArrowRight") {
-                walk.boy.run_right();
+impl Scene for WalkTheDog {
+    fn update(&mut self, keystate: &KeyState) -> SceneChange {
+        // the player's own keys pause a replay as well
+        if let Some(machine) = &self.machine {
+            let walk = machine.walk();
+            let actions = walk.actions.borrow();
+            if actions.just_pressed(keystate, Action::Pause) {
+                return SceneChange::Push(pause_scene(&actions, walk.sounds.audio().clone()));
             }
+        }
 
-            if keystate.is_pressed("ArrowUp") {
-                walk.boy.jump();
-            }
+        // once the replay runs out the player takes over, to start a game of their own
+        let replayed = self.replay_input.as_mut().and_then(Iterator::next);
+        if replayed.is_none() {
+            self.replay_input = None;
+        }
+        let keystate = replayed.as_ref().unwrap_or(keystate);
 
-            if keystate.is_pressed("ArrowDown") {
-                walk.boy.slide();
+        if let Some(machine) = self.machine.take() {
+            self.machine.replace(machine.update(keystate));
+        }
+        match &self.machine {
+            // GameOver only lasts until the machine's next update, so the walk has just ended
+            Some(WalkTheDogStateMachine::GameOver(state)) => {
+                SceneChange::Push(Box::new(GameOverScene::new(&state.walk)))
             }
+            _ => SceneChange::Stay,
+        }
+    }
 
-            let velocity = walk.velocity();
-
-            walk.boy.update();
-
-            // remove all obstacles that are out of screen
-            walk.obstacles.retain(|obstacle| obstacle.right() > 0);
-
-            // !NOTE: This is a workaround for borrow checker.
-            // Expect future Rust update to fix this issue.
-            // ref: https://stackoverflow.com/questions/64921625/closure-requires-unique-access-to-self-but-it-is-already-borrowed
-            let mut obstacles = std::mem::take(&mut walk.obstacles);
-            obstacles.iter_mut().for_each(|obstacle| {
-                obstacle.move_horizontally(velocity);
-                obstacle.check_intersection(&mut walk.boy);
-            });
-            walk.obstacles = obstacles;
-
-            let [bg1, bg2] = &mut walk.backgrounds;
-            bg1.move_horizontally(velocity);
-            bg2.move_horizontally(velocity);
-
-            if bg1.right() < 0 {
-                bg1.set_x(bg2.right());
-            }
-            if bg2.right() < 0 {
-                bg2.set_x(bg1.right());
-            }
+    fn draw(&self, renderer: &dyn Render, alpha: f32) {
+        renderer.clear(&Rect::new(Point::new(0, 0), WIDTH, HEIGHT));
 
-            if walk.timeline < TIMELINE_MINIMUM {
-                walk.generate_next_segment();
-            } else {
-                walk.timeline += velocity;
-            }
+        if let Some(machine) = &self.machine {
+            machine.draw(renderer, alpha);
         }
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        renderer.clear(&Rect::new(Point::new(0, 0), 600, 600));
+    fn set_paused(&mut self, paused: bool) {
+        if let Some(machine) = &self.machine {
+            pause_audio(machine.walk().sounds.audio().as_ref(), paused);
+        }
+    }
 
-        if let WalkTheDog::Loaded(walk) = self {
-            walk.backgrounds.iter().for_each(|bg| bg.draw(renderer));
-            walk.boy.draw(renderer);
-            walk.obstacles.iter().for_each(|obstacle| {
-                obstacle.draw(renderer);
-            });
+    fn shutdown(&mut self) {
+        if let Some(machine) = &self.machine {
+            machine.walk().shutdown();
         }
     }
 }
 
 pub trait Obstacle {
-    fn draw(&self, renderer: &Renderer);
-    fn check_intersection(&self, boy: &mut RedHatBoy);
+    fn draw(&self, renderer: &dyn Render, alpha: f32);
+    fn save_position(&mut self);
+    // Resolves collisions with the boy, reporting anything that earns points.
+    fn check_intersection(&mut self, boy: &mut RedHatBoy) -> Option<ScoreEvent>;
     fn move_horizontally(&mut self, x: i16);
     fn right(&self) -> i16;
+    fn collision_shape(&self) -> CollisionShape;
 }
 
 impl Obstacle for Platform {
-    fn draw(&self, renderer: &Renderer) {
+    fn draw(&self, renderer: &dyn Render, alpha: f32) {
+        let position = self.position.interpolate(&self.previous_position, alpha);
+        let (offset_x, offset_y) = (position.x - self.position.x, position.y - self.position.y);
         let mut x = 0;
         self.sprites.iter().for_each(|sprite| {
             self.sheet.draw(
@@ -370,22 +1300,21 @@ impl Obstacle for Platform {
                     sprite.frame.w,
                     sprite.frame.h,
                 ),
-                &Rect::new_from_x_y(
-                    self.position.x + x,
-                    self.position.y,
-                    sprite.frame.w,
-                    sprite.frame.h,
-                ),
+                &Rect::new_from_x_y(position.x + x, position.y, sprite.frame.w, sprite.frame.h),
             );
             x += sprite.frame.w;
         });
 
         // debug
         for collision_box in self.collision_boxes() {
-            renderer.draw_stroke_rect(&collision_box);
+            renderer.draw_stroke_rect(&collision_box.offset_by(offset_x, offset_y));
         }
     }
 
+    fn save_position(&mut self) {
+        self.previous_position = self.position;
+    }
+
     fn move_horizontally(&mut self, x: i16) {
         self.position.x += x;
         self.bounding_boxes
@@ -393,7 +1322,7 @@ impl Obstacle for Platform {
             .for_each(|cb| cb.set_x(cb.x() + x))
     }
 
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
+    fn check_intersection(&mut self, boy: &mut RedHatBoy) -> Option<ScoreEvent> {
         if let Some(box_to_land_on) = self
             .collision_boxes()
             .iter()
@@ -401,10 +1330,17 @@ impl Obstacle for Platform {
         {
             if boy.velocity_y() > 0 && boy.pos_y() < self.position.y {
                 boy.land_on(box_to_land_on.y());
+
+                // the boy keeps landing every frame he stands here, only the first one scores
+                if !self.landed_on {
+                    self.landed_on = true;
+                    return Some(ScoreEvent::LandedOnPlatform);
+                }
             } else {
                 boy.knock_out();
             }
         }
+        None
     }
 
     // Max right value of all collision boxes
@@ -415,36 +1351,59 @@ impl Obstacle for Platform {
             .max()
             .unwrap()
     }
+
+    fn collision_shape(&self) -> CollisionShape {
+        CollisionShape::Platform {
+            top: self.position.y,
+            boxes: self.bounding_boxes.clone(),
+        }
+    }
 }
 
 pub struct Barrier {
     image: Image,
+    cleared: bool,
 }
 
 impl Barrier {
     pub fn new(image: Image) -> Self {
-        Self { image }
+        Self {
+            image,
+            cleared: false,
+        }
     }
 }
 
 impl Obstacle for Barrier {
-    fn draw(&self, renderer: &Renderer) {
-        self.image.draw(renderer);
+    fn draw(&self, renderer: &dyn Render, alpha: f32) {
+        self.image.draw(renderer, alpha);
+    }
+
+    fn save_position(&mut self) {
+        self.image.save_position();
     }
 
     fn move_horizontally(&mut self, x: i16) {
         self.image.move_horizontally(x);
     }
 
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
+    fn check_intersection(&mut self, boy: &mut RedHatBoy) -> Option<ScoreEvent> {
         if boy.bounding_box().intersects(&self.image.bounding_box) {
             boy.knock_out();
+        } else if !self.cleared && self.right() < boy.center_x() {
+            self.cleared = true;
+            return Some(ScoreEvent::ClearedBarrier);
         }
+        None
     }
 
     fn right(&self) -> i16 {
         self.image.right()
     }
+
+    fn collision_shape(&self) -> CollisionShape {
+        CollisionShape::Barrier(self.image.bounding_box)
+    }
 }
 
 fn rightmost(obstacle_list: &Vec<Box<dyn Obstacle>>) -> i16 {
@@ -454,3 +1413,596 @@ fn rightmost(obstacle_list: &Vec<Box<dyn Obstacle>>) -> i16 {
         .max_by(|x, y| x.cmp(&y))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        DrawCall, Gesture, HeadlessAudio, HeadlessGameLoop, HeadlessRenderer, PlaybackStatus,
+        SceneStack, SheetRect,
+    };
+
+    fn cell(w: i16, h: i16) -> Cell {
+        Cell {
+            frame: SheetRect { x: 0, y: 0, w, h },
+            sprite_source_size: SheetRect { x: 0, y: 0, w, h },
+        }
+    }
+
+    fn sheet(names: impl Iterator<Item = String>, w: i16, h: i16) -> Sheet {
+        Sheet {
+            frames: names
+                .map(|name| (name, cell(w, h)))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    fn boy_sheet() -> Sheet {
+        let names = ["Idle", "Run", "Jump", "Slide", "Dead"]
+            .iter()
+            .flat_map(|name| (1..=12).map(move |n| format!("{} ({}).png", name, n)));
+        sheet(names, 70, 121)
+    }
+
+    fn new_walk(audio: Rc<HeadlessAudio>) -> WalkTheDog {
+        new_walk_with_storage(audio, Rc::new(MemoryStorage::new()))
+    }
+
+    fn new_walk_with_storage(audio: Rc<HeadlessAudio>, storage: Rc<dyn Storage>) -> WalkTheDog {
+        let tiles = sheet(
+            ["13.png", "14.png", "15.png"]
+                .iter()
+                .map(|name| name.to_string()),
+            128,
+            93,
+        );
+        let music = audio
+            .play_looping_sound(&SoundHandle::new(1), Bus::Music)
+            .unwrap();
+        let sounds = SoundManager::new(
+            audio,
+            music,
+            serde_json::from_str(include_str!("../static/sounds.json")).unwrap(),
+            HashMap::from([("jump".to_string(), SoundHandle::new(0))]),
+        )
+        .unwrap();
+        let boy = RedHatBoy::new(boy_sheet(), ImageHandle::new(0, 1200, 600));
+
+        let segments = Segments::new(
+            serde_json::from_str(include_str!("../static/segments.json")).unwrap(),
+            HashMap::from([("Stone.png".to_string(), ImageHandle::new(2, 50, 54))]),
+            Rc::new(SpriteSheet::new(ImageHandle::new(3, 1024, 1024), tiles)),
+        )
+        .unwrap();
+
+        let walk = Walk::new(
+            boy,
+            ImageHandle::new(1, 600, 600),
+            sounds,
+            segments,
+            storage,
+            serde_json::from_str(include_str!("../static/bindings.json")).unwrap(),
+            Some(7),
+        );
+
+        WalkTheDog {
+            machine: Some(WalkTheDogStateMachine::new(walk)),
+            ..WalkTheDog::new(Some(7))
+        }
+    }
+
+    fn walk(game: &WalkTheDog) -> &Walk {
+        game.machine
+            .as_ref()
+            .expect("Game should be initialized")
+            .walk()
+    }
+
+    #[test]
+    fn loading_screen_only_clears_the_canvas() {
+        let renderer = HeadlessRenderer::new();
+
+        WalkTheDog::new(None).draw(&renderer, 1.0);
+
+        assert_eq!(
+            renderer.calls(),
+            vec![DrawCall::Clear(Rect::new(Point::new(0, 0), 600, 600))]
+        );
+    }
+
+    #[test]
+    fn jumping_over_the_stone_then_onto_the_platform_lands_the_boy() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let mut game_loop = HeadlessGameLoop::new(new_walk(audio.clone()));
+
+        game_loop.run_scripted(120, |tick| match tick {
+            0..=4 => KeyState::with_pressed(&["ArrowRight"]),
+            5 | 61 => KeyState::with_pressed(&["ArrowUp"]),
+            _ => KeyState::new(),
+        });
+
+        let walk = walk(game_loop.game());
+        assert!(matches!(
+            walk.boy.state_machine,
+            RedHatBoyStateMachine::Running(_)
+        ));
+        // standing on top of the first platform
+        assert_eq!(walk.boy.pos_y(), 254);
+        // a barrier cleared and a platform landed on
+        assert_eq!(walk.score.total() - walk.score.distance(), 150);
+        assert_eq!(audio.played_on(Bus::Sfx), vec![SoundHandle::new(0); 2]);
+    }
+
+    #[test]
+    fn segments_are_judged_against_the_running_collision_box_even_while_idle() {
+        let mut boy = RedHatBoy::new(boy_sheet(), ImageHandle::new(0, 1200, 600));
+        let width = boy.running_collision_width();
+
+        boy.run_right();
+        boy.update();
+        assert_eq!(width, boy.collision_box().width);
+        assert!(width < boy.bounding_box().width);
+    }
+
+    #[test]
+    fn the_boy_reports_each_jump_and_landing_once() {
+        let mut boy = RedHatBoy::new(boy_sheet(), ImageHandle::new(0, 1200, 600));
+        boy.run_right();
+        boy.jump();
+        (0..60).for_each(|_| boy.update());
+
+        assert_eq!(
+            boy.take_events(),
+            vec![GameEvent::Jumped, GameEvent::Landed]
+        );
+        assert!(boy.take_events().is_empty());
+    }
+
+    #[test]
+    fn holding_jump_only_jumps_once() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let mut game_loop = HeadlessGameLoop::new(new_walk(audio.clone()));
+
+        game_loop.run(5, &KeyState::with_pressed(&["ArrowRight"]));
+        game_loop.run(60, &KeyState::with_pressed(&["ArrowUp"]));
+
+        assert_eq!(audio.played_on(Bus::Sfx), vec![SoundHandle::new(0)]);
+    }
+
+    #[test]
+    fn game_over_waits_for_new_game_and_restarts_the_walk() {
+        let mut game_loop = HeadlessGameLoop::new(new_walk(Rc::new(HeadlessAudio::new())));
+        let initial_timeline = walk(game_loop.game()).timeline;
+
+        game_loop.run(120, &KeyState::with_pressed(&["ArrowRight"]));
+        assert!(matches!(
+            game_loop.game().machine,
+            Some(WalkTheDogStateMachine::GameOver(_))
+        ));
+
+        game_loop.run(30, &KeyState::new());
+        assert!(matches!(
+            game_loop.game().machine,
+            Some(WalkTheDogStateMachine::GameOver(_))
+        ));
+
+        game_loop.run(1, &KeyState::with_pressed(&["Enter"]));
+        game_loop.run(1, &KeyState::new());
+        assert!(matches!(
+            game_loop.game().machine,
+            Some(WalkTheDogStateMachine::Ready(_))
+        ));
+
+        let walk = walk(game_loop.game());
+        assert!(matches!(
+            walk.boy.state_machine,
+            RedHatBoyStateMachine::Idle(_)
+        ));
+        assert_eq!(walk.timeline, initial_timeline);
+        assert_eq!(walk.obstacles.len(), 2);
+        assert_eq!(walk.backgrounds[0].position(), Point::new(0, 0));
+        assert_eq!(walk.backgrounds[1].position(), Point::new(600, 0));
+        assert_eq!(walk.score, Score::new());
+    }
+
+    fn obstacle_layout(game: &WalkTheDog) -> Vec<i16> {
+        walk(game)
+            .obstacles
+            .iter()
+            .map(|obstacle| obstacle.right())
+            .collect()
+    }
+
+    fn next_segments(game_loop: &mut HeadlessGameLoop<WalkTheDog>) -> Vec<i16> {
+        if let Some(WalkTheDogStateMachine::Ready(state)) = &mut game_loop.game_mut().machine {
+            (0..10).for_each(|_| state.walk.generate_next_segment());
+        }
+        obstacle_layout(game_loop.game())
+    }
+
+    #[test]
+    fn same_seed_generates_the_same_segments_after_a_new_game() {
+        let mut game_loop = HeadlessGameLoop::new(new_walk(Rc::new(HeadlessAudio::new())));
+        let first_run = next_segments(&mut game_loop);
+        game_loop.run(120, &KeyState::with_pressed(&["ArrowRight"]));
+        game_loop.run(1, &KeyState::with_pressed(&["Enter"]));
+        game_loop.run(1, &KeyState::new());
+        let second_run = next_segments(&mut game_loop);
+
+        assert_eq!(walk(game_loop.game()).seed, 7);
+        assert_eq!(first_run, second_run);
+    }
+
+    #[test]
+    fn keys_picked_on_the_game_over_screen_drive_the_walk_and_are_kept_in_storage() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let storage = Rc::new(MemoryStorage::new());
+        let mut game_loop =
+            HeadlessGameLoop::new(new_walk_with_storage(audio.clone(), storage.clone()));
+        game_loop.run(120, &KeyState::with_pressed(&["ArrowRight"]));
+
+        game_loop.run(1, &KeyState::with_pressed(&["KeyB"]));
+        // taken by pause, which doesn't pause the game while the player is picking keys
+        game_loop.run(1, &KeyState::with_pressed(&["Escape"]));
+        assert!(!audio.is_suspended());
+        game_loop.run(1, &KeyState::with_pressed(&["KeyL"]));
+        game_loop.run(1, &KeyState::with_pressed(&["KeyJ"]));
+        // taken by a new game, so the player is asked again
+        game_loop.run(1, &KeyState::with_pressed(&["Enter"]));
+        game_loop.run(1, &KeyState::with_pressed(&["KeyX"]));
+        assert!(matches!(
+            game_loop.game().machine,
+            Some(WalkTheDogStateMachine::GameOver(_))
+        ));
+        assert!(walk(game_loop.game())
+            .actions
+            .borrow()
+            .is_bound(Action::Slide, "KeyX"));
+        assert!(walk(game_loop.game())
+            .actions
+            .borrow()
+            .is_bound(Action::NewGame, "Enter"));
+
+        let jumps = audio.played_on(Bus::Sfx).len();
+        game_loop.run(1, &KeyState::with_pressed(&["Enter"]));
+        game_loop.run(5, &KeyState::with_pressed(&["KeyL"]));
+        game_loop.run(1, &KeyState::with_pressed(&["KeyJ"]));
+        assert_eq!(audio.played_on(Bus::Sfx).len(), jumps + 1);
+        game_loop.run(1, &KeyState::with_pressed(&["Escape"]));
+        assert!(audio.is_suspended());
+
+        let reloaded = new_walk_with_storage(Rc::new(HeadlessAudio::new()), storage);
+        let actions = walk(&reloaded).actions.borrow();
+        assert!(actions.is_bound(Action::Run, "KeyL"));
+        assert!(actions.is_bound(Action::Jump, "KeyJ"));
+        assert!(actions.is_bound(Action::Jump, "ArrowUp"));
+    }
+
+    // What the loop hands a tick after recognizing a gesture.
+    fn gesture(gesture: Gesture) -> KeyState {
+        let mut keystate = KeyState::new();
+        keystate.set_pressed(gesture.code());
+        keystate.set_released(gesture.code());
+        keystate
+    }
+
+    #[test]
+    fn a_tap_starts_a_new_game_after_a_knock_out() {
+        let mut game_loop = HeadlessGameLoop::new(new_walk(Rc::new(HeadlessAudio::new())));
+        game_loop.run(120, &KeyState::with_pressed(&["ArrowRight"]));
+        assert!(matches!(
+            game_loop.game().machine,
+            Some(WalkTheDogStateMachine::GameOver(_))
+        ));
+
+        game_loop.step(&gesture(Gesture::Tap));
+        game_loop.step(&KeyState::new());
+
+        assert!(matches!(
+            game_loop.game().machine,
+            Some(WalkTheDogStateMachine::Ready(_))
+        ));
+    }
+
+    #[test]
+    fn taps_start_the_run_and_jump_and_a_swipe_slides() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let mut game_loop = HeadlessGameLoop::new(new_walk(audio.clone()));
+
+        game_loop.step(&gesture(Gesture::Tap));
+        assert!(matches!(
+            walk(game_loop.game()).boy.state_machine,
+            RedHatBoyStateMachine::Running(_)
+        ));
+
+        game_loop.step(&gesture(Gesture::Tap));
+        assert!(matches!(
+            walk(game_loop.game()).boy.state_machine,
+            RedHatBoyStateMachine::Jumping(_)
+        ));
+        assert_eq!(audio.played_on(Bus::Sfx), vec![SoundHandle::new(0)]);
+
+        let mut game_loop = HeadlessGameLoop::new(new_walk(Rc::new(HeadlessAudio::new())));
+        game_loop.step(&gesture(Gesture::Tap));
+        game_loop.step(&gesture(Gesture::SwipeDown));
+        assert!(matches!(
+            walk(game_loop.game()).boy.state_machine,
+            RedHatBoyStateMachine::Sliding(_)
+        ));
+    }
+
+    #[test]
+    fn a_recorded_run_replays_frame_for_frame() {
+        let storage = Rc::new(MemoryStorage::new());
+        let mut game_loop = HeadlessGameLoop::new(new_walk_with_storage(
+            Rc::new(HeadlessAudio::new()),
+            storage.clone(),
+        ));
+        while !walk(game_loop.game()).knocked_out() {
+            game_loop.run_scripted(1, |tick| match tick {
+                0..=4 => KeyState::with_pressed(&["ArrowRight"]),
+                5 | 61 => KeyState::with_pressed(&["ArrowUp"]),
+                _ => KeyState::new(),
+            });
+        }
+
+        let saved: RunRecording = storage::load(storage.as_ref(), LAST_RUN_KEY)
+            .unwrap()
+            .unwrap();
+        let file = serde_json::to_string(&saved).unwrap();
+        let run: RunRecording = serde_json::from_str(&file).unwrap();
+        let ticks = run.input.keystates().count() as u32;
+        assert_eq!(ticks, game_loop.ticks());
+        assert_eq!(run.input.seed(), 7);
+
+        let mut replay = new_walk(Rc::new(HeadlessAudio::new()));
+        replay.replay_input = Some(run.input_per_tick());
+        let mut replay_loop = HeadlessGameLoop::new(replay);
+        // the player's input is ignored while the replay runs
+        replay_loop.run(ticks, &KeyState::with_pressed(&["ArrowUp"]));
+
+        assert!(walk(replay_loop.game()).knocked_out());
+        assert_eq!(walk(replay_loop.game()).score, walk(game_loop.game()).score);
+        let original = HeadlessRenderer::new();
+        let replayed = HeadlessRenderer::new();
+        game_loop.draw(&original);
+        replay_loop.draw(&replayed);
+        assert_eq!(replayed.calls(), original.calls());
+
+        replay_loop.run(1, &KeyState::with_pressed(&["Enter"]));
+        replay_loop.run(1, &KeyState::new());
+        assert!(matches!(
+            replay_loop.game().machine,
+            Some(WalkTheDogStateMachine::Ready(_))
+        ));
+    }
+
+    #[test]
+    fn mixer_changes_are_applied_and_kept_in_storage() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let storage = Rc::new(MemoryStorage::new());
+        let mut game_loop =
+            HeadlessGameLoop::new(new_walk_with_storage(audio.clone(), storage.clone()));
+
+        let mut mix = audio.mix();
+        mix.set_muted(Bus::Music, true);
+        mix.set_gain(Bus::Sfx, 0.5);
+        set_mix(audio.as_ref(), storage.as_ref(), &mix);
+
+        assert_eq!(audio.mix(), mix);
+        assert_eq!(
+            storage::load::<MixerSettings>(storage.as_ref(), MIXER_KEY).unwrap(),
+            Some(mix)
+        );
+
+        game_loop.run(5, &KeyState::with_pressed(&["ArrowRight"]));
+        game_loop.run(1, &KeyState::with_pressed(&["ArrowUp"]));
+        assert_eq!(audio.played_on(Bus::Sfx), vec![SoundHandle::new(0)]);
+    }
+
+    #[test]
+    fn the_mute_key_toggles_every_sound_and_is_kept_in_storage() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let storage = Rc::new(MemoryStorage::new());
+        let mut game_loop =
+            HeadlessGameLoop::new(new_walk_with_storage(audio.clone(), storage.clone()));
+
+        game_loop.run(1, &KeyState::with_pressed(&["KeyM"]));
+        assert!(audio.mix().master.muted);
+        assert_eq!(
+            storage::load::<MixerSettings>(storage.as_ref(), MIXER_KEY).unwrap(),
+            Some(audio.mix())
+        );
+
+        game_loop.run(1, &KeyState::new());
+        game_loop.run(1, &KeyState::with_pressed(&["KeyM"]));
+        assert!(!audio.mix().master.muted);
+    }
+
+    #[test]
+    fn the_settings_screen_changes_and_mutes_a_single_bus() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let storage = Rc::new(MemoryStorage::new());
+        let mut game_loop =
+            HeadlessGameLoop::new(new_walk_with_storage(audio.clone(), storage.clone()));
+        game_loop.run(120, &KeyState::with_pressed(&["ArrowRight"]));
+
+        for code in ["KeyO", "ArrowDown", "ArrowRight", "ArrowRight", "KeyM"] {
+            game_loop.run(1, &KeyState::with_pressed(&[code]));
+            game_loop.run(1, &KeyState::new());
+        }
+        let mix = audio.mix();
+        assert!((mix.music.gain - 0.3).abs() < 1e-6);
+        assert!(mix.music.muted);
+        assert_eq!(mix.sfx, MixerSettings::default().sfx);
+        assert_eq!(mix.master, MixerSettings::default().master);
+        assert_eq!(
+            storage::load::<MixerSettings>(storage.as_ref(), MIXER_KEY).unwrap(),
+            Some(mix)
+        );
+
+        let renderer = HeadlessRenderer::new();
+        game_loop.draw(&renderer);
+        assert!(renderer
+            .calls()
+            .iter()
+            .any(|call| matches!(call, DrawCall::Text { text, .. } if text == "> Music: muted"),));
+
+        // back on the game over screen, which starts a new game as before
+        game_loop.run(1, &KeyState::with_pressed(&["KeyO"]));
+        game_loop.run(1, &KeyState::with_pressed(&["Enter"]));
+        game_loop.run(1, &KeyState::new());
+        assert!(matches!(
+            game_loop.game().machine,
+            Some(WalkTheDogStateMachine::Ready(_))
+        ));
+    }
+
+    #[test]
+    fn shutting_down_stops_the_music_and_closes_the_audio() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let mut scenes = SceneStack::new(Box::new(new_walk(audio.clone())));
+
+        scenes.shutdown();
+
+        assert_eq!(audio.playbacks()[0].status, PlaybackStatus::Stopped);
+        assert_eq!(audio.state(), AudioState::Closed);
+    }
+
+    #[test]
+    fn music_fades_out_and_pauses_on_game_over_and_picks_up_for_a_new_game() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let mut game_loop = HeadlessGameLoop::new(new_walk(audio.clone()));
+        let music = || audio.playbacks()[0];
+        assert_eq!(music().status, PlaybackStatus::Playing);
+        assert!(music().looping);
+
+        game_loop.run(120, &KeyState::with_pressed(&["ArrowRight"]));
+        assert_eq!(
+            music().fade,
+            Some((0.0, MUSIC_FADE_OUT_SECONDS, Fade::Exponential))
+        );
+        assert_eq!(music().status, PlaybackStatus::Playing);
+
+        game_loop.run(120, &KeyState::new());
+        assert_eq!(music().status, PlaybackStatus::Paused);
+
+        game_loop.run(1, &KeyState::with_pressed(&["Enter"]));
+        game_loop.run(1, &KeyState::new());
+        assert_eq!(
+            music().fade,
+            Some((1.0, MUSIC_FADE_IN_SECONDS, Fade::Linear))
+        );
+        assert_eq!(music().status, PlaybackStatus::Playing);
+        assert_eq!(audio.played_on(Bus::Music), vec![SoundHandle::new(1)]);
+    }
+
+    #[test]
+    fn game_over_saves_and_shows_the_high_score() {
+        let storage = Rc::new(MemoryStorage::new());
+        let mut game_loop = HeadlessGameLoop::new(new_walk_with_storage(
+            Rc::new(HeadlessAudio::new()),
+            storage.clone(),
+        ));
+
+        game_loop.run(120, &KeyState::with_pressed(&["ArrowRight"]));
+
+        let saved = HighScores::load(storage.as_ref()).unwrap();
+        assert_eq!(saved.entries().len(), 1);
+        assert_eq!(
+            saved.entries()[0].score,
+            walk(game_loop.game()).score.total()
+        );
+
+        let renderer = HeadlessRenderer::new();
+        game_loop.draw(&renderer);
+        let expected = format!(
+            "1. {} ({} m)",
+            saved.entries()[0].score,
+            saved.entries()[0].distance
+        );
+        assert!(renderer.calls().iter().any(|call| matches!(
+            call,
+            DrawCall::Text { text, .. } if *text == expected
+        )));
+    }
+
+    #[test]
+    fn hud_shows_score_and_distance_every_frame() {
+        let mut game_loop = HeadlessGameLoop::new(new_walk(Rc::new(HeadlessAudio::new())));
+        game_loop.run(6, &KeyState::with_pressed(&["ArrowRight"]));
+
+        let renderer = HeadlessRenderer::new();
+        game_loop.draw(&renderer);
+
+        let texts: Vec<String> = renderer
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                DrawCall::Text { text, .. } => Some(text),
+                _ => None,
+            })
+            .collect();
+        // five walking ticks at four pixels each
+        assert_eq!(texts, vec!["Score: 2".to_string(), "2 m".to_string()]);
+    }
+
+    #[test]
+    fn hud_asks_for_a_gesture_until_audio_is_unlocked() {
+        let audio = Rc::new(HeadlessAudio::new());
+        audio.set_locked(true);
+        let game_loop = HeadlessGameLoop::new(new_walk(audio.clone()));
+        let shows_hint = |game_loop: &HeadlessGameLoop<WalkTheDog>| {
+            let renderer = HeadlessRenderer::new();
+            game_loop.draw(&renderer);
+            renderer.calls().iter().any(
+                |call| matches!(call, DrawCall::Text { text, .. } if text == UNLOCK_AUDIO_HINT),
+            )
+        };
+
+        assert!(shows_hint(&game_loop));
+
+        audio.set_locked(false);
+        assert!(!shows_hint(&game_loop));
+    }
+
+    #[test]
+    fn pausing_freezes_the_walk_and_suspends_audio() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let mut game_loop = HeadlessGameLoop::new(new_walk(audio.clone()));
+        game_loop.run(10, &KeyState::with_pressed(&["ArrowRight"]));
+        let distance = walk(game_loop.game()).score.distance();
+
+        game_loop.run(1, &KeyState::with_pressed(&["Escape"]));
+        game_loop.run(30, &KeyState::with_pressed(&["ArrowRight"]));
+        assert!(audio.is_suspended());
+        assert_eq!(walk(game_loop.game()).score.distance(), distance);
+
+        let renderer = HeadlessRenderer::new();
+        game_loop.draw(&renderer);
+        assert!(renderer
+            .calls()
+            .iter()
+            .any(|call| matches!(call, DrawCall::Text { text, .. } if text == "Paused"),));
+
+        game_loop.run(1, &KeyState::with_pressed(&["KeyP"]));
+        game_loop.run(10, &KeyState::with_pressed(&["ArrowRight"]));
+        assert!(!audio.is_suspended());
+        assert!(walk(game_loop.game()).score.distance() > distance);
+    }
+
+    #[test]
+    fn drawing_between_updates_interpolates_the_background() {
+        let mut game_loop = HeadlessGameLoop::new(new_walk(Rc::new(HeadlessAudio::new())));
+        game_loop.run(6, &KeyState::with_pressed(&["ArrowRight"]));
+
+        let renderer = HeadlessRenderer::new();
+        game_loop.draw_interpolated(&renderer, 0.5);
+
+        // halfway between the last two updates, which moved it from -16 to -20
+        assert!(renderer.calls().contains(&DrawCall::EntireImage {
+            image: ImageHandle::new(1, 600, 600),
+            position: Point::new(-18, 0),
+        }));
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::engine::KeyState;
+use crate::storage::{self, Storage};
+
+const BINDINGS_KEY: &str = "walk_the_dog.bindings";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Run,
+    Jump,
+    Slide,
+    Pause,
+    NewGame,
+    Rebind,
+    Mute,
+    Settings,
+}
+
+impl Action {
+    // The ones players pick their own keys for. The rest keep theirs, so starting a game,
+    // pausing and rebinding are always within reach.
+    pub fn is_rebindable(self) -> bool {
+        matches!(self, Action::Run | Action::Jump | Action::Slide)
+    }
+}
+
+// What players know a code by, e.g. KeyB is B and GamepadButton0 is the pad's A.
+pub fn code_name(code: &str) -> String {
+    const PAD_BUTTONS: [&str; 16] = [
+        "A", "B", "X", "Y", "LB", "RB", "LT", "RT", "Select", "Start", "LS", "RS", "Up", "Down",
+        "Left", "Right",
+    ];
+
+    if let Some(button) = code.strip_prefix("GamepadButton") {
+        if let Some(name) = button
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| PAD_BUTTONS.get(i))
+        {
+            return name.to_string();
+        }
+    }
+    match code {
+        "Tap" => "tap".to_string(),
+        "SwipeDown" => "swipe down".to_string(),
+        _ => code
+            .strip_prefix("Key")
+            .or_else(|| code.strip_prefix("Digit"))
+            .unwrap_or(code)
+            .to_string(),
+    }
+}
+
+// The key codes bound to each action, any of them triggers it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ActionMap {
+    bindings: HashMap<Action, Vec<String>>,
+}
+
+// What the player changed from the defaults, code by code, so codes added to the defaults
+// since still apply.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Rebindings {
+    #[serde(default)]
+    added: HashMap<Action, Vec<String>>,
+    #[serde(default)]
+    removed: HashMap<Action, Vec<String>>,
+}
+
+impl ActionMap {
+    pub fn load(storage: &dyn Storage, defaults: ActionMap) -> Result<Self> {
+        let mut actions = defaults;
+        if let Some(stored) = storage::load::<Rebindings>(storage, BINDINGS_KEY)? {
+            // anything saved against the actions that keep their keys is left out, as are
+            // codes one of those actions has been given since
+            for (action, codes) in stored.removed {
+                if action.is_rebindable() {
+                    codes.iter().for_each(|code| actions.unbind(action, code));
+                }
+            }
+            for (action, codes) in stored.added {
+                for code in codes {
+                    let _ = actions.bind(action, &code);
+                }
+            }
+        }
+        Ok(actions)
+    }
+
+    pub fn save(&self, storage: &dyn Storage, defaults: &ActionMap) -> Result<()> {
+        let mut rebindings = Rebindings::default();
+        for (action, codes) in &self.bindings {
+            let added: Vec<String> = codes
+                .iter()
+                .filter(|code| !defaults.is_bound(*action, code))
+                .cloned()
+                .collect();
+            if !added.is_empty() {
+                rebindings.added.insert(*action, added);
+            }
+        }
+        for (action, codes) in &defaults.bindings {
+            let removed: Vec<String> = codes
+                .iter()
+                .filter(|code| !self.is_bound(*action, code))
+                .cloned()
+                .collect();
+            if !removed.is_empty() {
+                rebindings.removed.insert(*action, removed);
+            }
+        }
+        storage::save(storage, BINDINGS_KEY, &rebindings)
+    }
+
+    pub fn bindings(&self, action: Action) -> &[String] {
+        self.bindings
+            .get(&action)
+            .map_or(&[], |codes| codes.as_slice())
+    }
+
+    pub fn is_bound(&self, action: Action, code: &str) -> bool {
+        self.bindings(action).iter().any(|bound| bound == code)
+    }
+
+    // Binding a key takes it away from the other rebindable actions. Only the defaults share
+    // keys, like a tap both starting the run and jumping. Keys the other actions have are
+    // refused, so they can't be taken from them.
+    pub fn bind(&mut self, action: Action, code: &str) -> Result<()> {
+        if !action.is_rebindable() {
+            return Err(anyhow!("{:?} keeps its keys", action));
+        }
+        if let Some((fixed, _)) = self.bindings.iter().find(|(other, codes)| {
+            !other.is_rebindable() && codes.iter().any(|bound| bound == code)
+        }) {
+            return Err(anyhow!("{} is taken by {:?}", code, fixed));
+        }
+
+        self.bindings
+            .values_mut()
+            .for_each(|codes| codes.retain(|bound| bound != code));
+        self.bindings.entry(action).or_default().push(code.into());
+        Ok(())
+    }
+
+    // Every code bound to the action by name, e.g. "Enter, A or tap".
+    pub fn describe(&self, action: Action) -> String {
+        let names: Vec<String> = self
+            .bindings(action)
+            .iter()
+            .map(|code| code_name(code))
+            .collect();
+        match names.split_last() {
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+            None => String::new(),
+        }
+    }
+
+    pub fn unbind(&mut self, action: Action, code: &str) {
+        if let Some(codes) = self.bindings.get_mut(&action) {
+            codes.retain(|bound| bound != code);
+        }
+    }
+
+    pub fn is_pressed(&self, keystate: &KeyState, action: Action) -> bool {
+        self.bindings(action)
+            .iter()
+            .any(|code| keystate.is_pressed(code))
+    }
+
+    pub fn just_pressed(&self, keystate: &KeyState, action: Action) -> bool {
+        self.bindings(action)
+            .iter()
+            .any(|code| keystate.just_pressed(code))
+    }
+
+    // Held, or pressed since the last tick even if already let go, which is all a tap or
+    // a swipe ever is.
+    pub fn triggered(&self, keystate: &KeyState, action: Action) -> bool {
+        self.is_pressed(keystate, action) || self.just_pressed(keystate, action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn defaults() -> ActionMap {
+        serde_json::from_str(include_str!("../static/bindings.json")).unwrap()
+    }
+
+    #[test]
+    fn every_binding_triggers_its_action() {
+        let actions = defaults();
+
+        assert!(actions.is_pressed(&KeyState::with_pressed(&["ArrowUp"]), Action::Jump));
+        assert!(actions.is_pressed(&KeyState::with_pressed(&["KeyW"]), Action::Jump));
+        assert!(actions.just_pressed(&KeyState::with_pressed(&["KeyD"]), Action::Run));
+        assert!(!actions.is_pressed(&KeyState::with_pressed(&["KeyW"]), Action::Slide));
+        assert!(actions.is_bound(Action::Pause, "Escape"));
+    }
+
+    #[test]
+    fn binding_a_key_moves_it_from_its_old_action() {
+        let mut actions = defaults();
+
+        actions.bind(Action::Slide, "Space").unwrap();
+
+        assert!(actions.is_bound(Action::Slide, "Space"));
+        assert!(!actions.is_bound(Action::Jump, "Space"));
+        assert!(actions.is_bound(Action::Jump, "ArrowUp"));
+    }
+
+    #[test]
+    fn rebound_actions_round_trip_through_storage() {
+        let storage = MemoryStorage::new();
+        let mut actions = ActionMap::load(&storage, defaults()).unwrap();
+        assert_eq!(actions, defaults());
+
+        actions.unbind(Action::Jump, "ArrowUp");
+        actions.bind(Action::Jump, "KeyK").unwrap();
+        actions.save(&storage, &defaults()).unwrap();
+
+        let loaded = ActionMap::load(&storage, defaults()).unwrap();
+        assert_eq!(loaded, actions);
+        assert!(!loaded.is_bound(Action::Jump, "ArrowUp"));
+        assert!(loaded.is_bound(Action::Jump, "KeyK"));
+    }
+
+    #[test]
+    fn codes_added_to_the_defaults_survive_saved_bindings() {
+        let storage = MemoryStorage::new();
+        let mut actions = defaults();
+        actions.bind(Action::Jump, "KeyK").unwrap();
+        actions.save(&storage, &defaults()).unwrap();
+
+        let mut newer_defaults = defaults();
+        newer_defaults.bind(Action::Jump, "KeyI").unwrap();
+        newer_defaults.bind(Action::Slide, "KeyK").unwrap();
+        let loaded = ActionMap::load(&storage, newer_defaults).unwrap();
+
+        assert!(loaded.is_bound(Action::Jump, "KeyI"));
+        assert!(loaded.is_bound(Action::Jump, "KeyK"));
+        assert!(loaded.is_bound(Action::Jump, "ArrowUp"));
+        assert!(!loaded.is_bound(Action::Slide, "KeyK"));
+    }
+
+    #[test]
+    fn a_new_game_stays_within_reach_of_keyboards_and_touch_whatever_is_rebound() {
+        let mut actions = defaults();
+        let codes: Vec<String> = [
+            Action::Run,
+            Action::Jump,
+            Action::Slide,
+            Action::Pause,
+            Action::NewGame,
+            Action::Rebind,
+            Action::Mute,
+            Action::Settings,
+        ]
+        .iter()
+        .flat_map(|action| actions.bindings(*action).to_vec())
+        .collect();
+
+        for action in [Action::Run, Action::Jump, Action::Slide, Action::NewGame] {
+            for code in &codes {
+                let _ = actions.bind(action, code);
+            }
+        }
+
+        assert_eq!(
+            actions.bindings(Action::NewGame),
+            defaults().bindings(Action::NewGame)
+        );
+        assert!(actions.is_bound(Action::Rebind, "KeyB"));
+        assert!(actions.is_bound(Action::Pause, "Escape"));
+        assert!(actions.bind(Action::Run, "Enter").is_err());
+        assert!(actions.bind(Action::NewGame, "KeyN").is_err());
+    }
+
+    #[test]
+    fn keys_are_described_by_the_names_players_know() {
+        let actions = defaults();
+
+        assert_eq!(actions.describe(Action::NewGame), "Enter, A or tap");
+        assert_eq!(actions.describe(Action::Rebind), "B or Y");
+    }
+}
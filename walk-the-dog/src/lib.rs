@@ -1,12 +1,18 @@
+use std::cell::RefCell;
 use wasm_bindgen::prelude::*;
 
 #[macro_use]
 mod browser;
 mod engine;
 mod game;
+mod input;
+mod reachability;
 mod state;
+mod score;
 mod segments;
+mod sfx;
 mod sound;
+mod storage;
 
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
 // allocator.
@@ -15,16 +21,53 @@ mod sound;
 #[cfg(feature = "wee_alloc")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+
+thread_local! {
+    static GAME_LOOP: RefCell<Option<engine::GameLoopHandle>> = const { RefCell::new(None) };
+}
+
 // This is like the `main` function, except for JavaScript.
 #[wasm_bindgen(start)]
 pub fn main_js() -> Result<(), JsValue> {
     console_error_panic_hook::set_once();
 
     browser::spawn_local(async move {
-        let game = game::WalkTheDog::new();
+        let seed = browser::query_param("seed")
+            .ok()
+            .flatten()
+            .and_then(|seed| seed.parse().ok());
+        // ?replay=last plays back the player's last run, any other value is a URL to fetch one from
+        let replay = match browser::query_param("replay").ok().flatten() {
+            Some(source) => match game::RunRecording::load(&source).await {
+                Ok(run) => Some(run),
+                Err(err) => {
+                    error!("Could not load the replay: {:#?}", err);
+                    None
+                }
+            },
+            None => None,
+        };
+        let game = match replay {
+            Some(run) => game::WalkTheDog::replaying(run),
+            None => game::WalkTheDog::new(seed),
+        };
 
-        engine::GameLoop::start(game).await.expect("Failed to start game loop");
+        let handle = engine::GameLoop::start(game, engine::GameLoopConfig::default())
+            .await
+            .expect("Failed to start game loop");
+        GAME_LOOP.with(|game_loop| game_loop.borrow_mut().replace(handle));
     });
     
     Ok(())
 }
+
+// For pages that take the game out again, stops it and lets go of everything it holds.
+#[wasm_bindgen]
+pub fn stop() -> Result<(), JsValue> {
+    match GAME_LOOP.with(|game_loop| game_loop.borrow_mut().take()) {
+        Some(handle) => handle
+            .stop()
+            .map_err(|err| JsValue::from_str(&format!("{:#?}", err))),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+
+use crate::engine::Rect;
+use crate::game::Obstacle;
+use crate::state::red_hat_boy_states::{
+    FLOOR, GRAVITY, JUMP_SPEED, PLAYER_HEIGHT, RUNNING_SPEED, TERMINAL_VELOCITY,
+};
+
+// What an obstacle looks like to the boy when he runs into it.
+pub enum CollisionShape {
+    // Landing on top of a platform is fine, hitting it anywhere else knocks the boy out.
+    Platform { top: i16, boxes: Vec<Rect> },
+    Barrier(Rect),
+}
+
+impl CollisionShape {
+    fn boxes(&self) -> &[Rect] {
+        match self {
+            CollisionShape::Platform { boxes, .. } => boxes,
+            CollisionShape::Barrier(bounding_box) => std::slice::from_ref(bounding_box),
+        }
+    }
+}
+
+// The parts of the boy's state that matter for getting through a segment.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Jumper {
+    y: i16,
+    velocity_y: i16,
+    airborne: bool,
+}
+
+impl Jumper {
+    fn running() -> Self {
+        Jumper {
+            y: FLOOR,
+            velocity_y: 0,
+            airborne: false,
+        }
+    }
+
+    // The boy can only jump while he's running, on the floor or on a platform.
+    fn choices(self) -> Vec<Jumper> {
+        if self.airborne {
+            vec![self]
+        } else {
+            vec![
+                self,
+                Jumper {
+                    velocity_y: JUMP_SPEED,
+                    airborne: true,
+                    ..self
+                },
+            ]
+        }
+    }
+
+    // Same order as a walk tick: the boy moves first, then every obstacle checks for a hit.
+    fn update(mut self, x: i16, width: i16, shapes: &[CollisionShape]) -> Option<Jumper> {
+        if self.velocity_y + GRAVITY < TERMINAL_VELOCITY {
+            self.velocity_y += GRAVITY;
+        }
+        self.y = (self.y + self.velocity_y).min(FLOOR);
+        if self.y >= FLOOR {
+            self.airborne = false;
+        }
+
+        for shape in shapes {
+            let bounding_box = Rect::new_from_x_y(x, self.y, width, PLAYER_HEIGHT);
+
+            match shape {
+                CollisionShape::Platform { top, boxes } => {
+                    if let Some(platform_box) = boxes.iter().find(|cb| bounding_box.intersects(cb))
+                    {
+                        if self.velocity_y > 0 && self.y < *top {
+                            self.y = platform_box.y() - PLAYER_HEIGHT;
+                            self.airborne = false;
+                        } else {
+                            return None;
+                        }
+                    }
+                }
+                CollisionShape::Barrier(barrier_box) => {
+                    if bounding_box.intersects(barrier_box) {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(self)
+    }
+}
+
+// Runs the boy through the obstacles from the floor, trying a jump on every tick he could
+// take one, and reports whether any of those runs gets him past all of them.
+// Sliding isn't simulated, so a layout that needs it is rejected.
+pub fn is_reachable(obstacles: &[Box<dyn Obstacle>], boy_width: i16) -> bool {
+    let shapes: Vec<CollisionShape> = obstacles
+        .iter()
+        .map(|obstacle| obstacle.collision_shape())
+        .collect();
+    is_reachable_through(&shapes, boy_width)
+}
+
+fn is_reachable_through(shapes: &[CollisionShape], boy_width: i16) -> bool {
+    let boxes = shapes.iter().flat_map(|shape| shape.boxes());
+    let (left, right) = boxes.fold((i16::MAX, i16::MIN), |(left, right), cb| {
+        (left.min(cb.x()), right.max(cb.right()))
+    });
+
+    // far enough back for a whole jump before the first obstacle
+    let airtime = 2 * JUMP_SPEED.abs() / GRAVITY;
+    let mut x = left.saturating_sub(boy_width + airtime * RUNNING_SPEED);
+    let mut jumpers = HashSet::from([Jumper::running()]);
+
+    while x <= right {
+        x += RUNNING_SPEED;
+        jumpers = jumpers
+            .into_iter()
+            .flat_map(Jumper::choices)
+            .filter_map(|jumper| jumper.update(x, boy_width, shapes))
+            .collect();
+
+        if jumpers.is_empty() {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOY_WIDTH: i16 = 70;
+
+    fn platform(x: i16, y: i16) -> CollisionShape {
+        CollisionShape::Platform {
+            top: y,
+            boxes: vec![Rect::new_from_x_y(x, y, 384, 93)],
+        }
+    }
+
+    #[test]
+    fn a_stone_the_boy_can_jump_over_is_reachable() {
+        let shapes = [CollisionShape::Barrier(Rect::new_from_x_y(
+            150, 546, 50, 54,
+        ))];
+
+        assert!(is_reachable_through(&shapes, BOY_WIDTH));
+    }
+
+    #[test]
+    fn a_wall_taller_than_the_jump_is_rejected() {
+        let shapes = [CollisionShape::Barrier(Rect::new_from_x_y(
+            150, 100, 50, 500,
+        ))];
+
+        assert!(!is_reachable_through(&shapes, BOY_WIDTH));
+    }
+
+    #[test]
+    fn landing_on_a_platform_to_get_over_a_stone_is_reachable() {
+        let shapes = [
+            platform(200, 375),
+            CollisionShape::Barrier(Rect::new_from_x_y(400, 546, 50, 54)),
+        ];
+
+        assert!(is_reachable_through(&shapes, BOY_WIDTH));
+    }
+
+    #[test]
+    fn a_stone_wider_than_any_jump_is_rejected() {
+        let shapes = [CollisionShape::Barrier(Rect::new_from_x_y(
+            150, 546, 600, 54,
+        ))];
+
+        assert!(!is_reachable_through(&shapes, BOY_WIDTH));
+    }
+}
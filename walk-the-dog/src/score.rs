@@ -0,0 +1,161 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{self, Storage};
+
+const PLATFORM_POINTS: u32 = 50;
+const BARRIER_POINTS: u32 = 100;
+// Horizontal pixels the boy has to cover for one point of distance.
+const PIXELS_PER_POINT: u32 = 10;
+const HIGH_SCORES_KEY: &str = "walk_the_dog.high_scores";
+pub const HIGH_SCORE_ENTRIES: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreEvent {
+    LandedOnPlatform,
+    ClearedBarrier,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Score {
+    distance: u32,
+    platforms_landed: u32,
+    barriers_cleared: u32,
+}
+
+impl Score {
+    pub fn new() -> Self {
+        Score::default()
+    }
+
+    // The walk scrolls to the left, so velocity is negative while the boy runs forward.
+    pub fn add_distance(&mut self, velocity: i16) {
+        if velocity < 0 {
+            self.distance += velocity.unsigned_abs() as u32;
+        }
+    }
+
+    pub fn record(&mut self, event: ScoreEvent) {
+        match event {
+            ScoreEvent::LandedOnPlatform => self.platforms_landed += 1,
+            ScoreEvent::ClearedBarrier => self.barriers_cleared += 1,
+        }
+    }
+
+    pub fn distance(&self) -> u32 {
+        self.distance / PIXELS_PER_POINT
+    }
+
+    pub fn total(&self) -> u32 {
+        self.distance()
+            + self.platforms_landed * PLATFORM_POINTS
+            + self.barriers_cleared * BARRIER_POINTS
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HighScore {
+    pub score: u32,
+    pub distance: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HighScores {
+    entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    pub fn load(storage: &dyn Storage) -> Result<Self> {
+        Ok(storage::load(storage, HIGH_SCORES_KEY)?.unwrap_or_default())
+    }
+
+    pub fn save(&self, storage: &dyn Storage) -> Result<()> {
+        storage::save(storage, HIGH_SCORES_KEY, self)
+    }
+
+    // Returns the rank the score was placed at, or None when it didn't make the table.
+    pub fn insert(&mut self, score: &Score) -> Option<usize> {
+        let rank = self
+            .entries
+            .iter()
+            .position(|entry| score.total() > entry.score)
+            .unwrap_or(self.entries.len());
+
+        if rank >= HIGH_SCORE_ENTRIES {
+            return None;
+        }
+
+        self.entries.insert(
+            rank,
+            HighScore {
+                score: score.total(),
+                distance: score.distance(),
+            },
+        );
+        self.entries.truncate(HIGH_SCORE_ENTRIES);
+        Some(rank)
+    }
+
+    pub fn entries(&self) -> &[HighScore] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn total_combines_distance_and_obstacle_bonuses() {
+        let mut score = Score::new();
+
+        score.add_distance(-4);
+        score.add_distance(-16);
+        score.add_distance(0);
+        score.record(ScoreEvent::LandedOnPlatform);
+        score.record(ScoreEvent::ClearedBarrier);
+        score.record(ScoreEvent::ClearedBarrier);
+
+        assert_eq!(score.distance(), 2);
+        assert_eq!(score.total(), 2 + PLATFORM_POINTS + 2 * BARRIER_POINTS);
+    }
+
+    fn score_with_distance(pixels: i16) -> Score {
+        let mut score = Score::new();
+        score.add_distance(-pixels);
+        score
+    }
+
+    #[test]
+    fn high_scores_keep_the_best_entries_in_order() {
+        let mut high_scores = HighScores::default();
+
+        for pixels in [300, 100, 500, 200, 400, 600] {
+            high_scores.insert(&score_with_distance(pixels));
+        }
+
+        assert_eq!(high_scores.insert(&score_with_distance(50)), None);
+        assert_eq!(high_scores.insert(&score_with_distance(350)), Some(3));
+        assert_eq!(
+            high_scores
+                .entries()
+                .iter()
+                .map(|entry| entry.score)
+                .collect::<Vec<_>>(),
+            vec![60, 50, 40, 35, 30]
+        );
+    }
+
+    #[test]
+    fn high_scores_round_trip_through_storage() {
+        let storage = MemoryStorage::new();
+        assert_eq!(HighScores::load(&storage).unwrap(), HighScores::default());
+
+        let mut high_scores = HighScores::default();
+        high_scores.insert(&score_with_distance(120));
+        high_scores.save(&storage).unwrap();
+
+        assert_eq!(HighScores::load(&storage).unwrap(), high_scores);
+    }
+}
@@ -1,92 +1,498 @@
-use std::rc::Rc;
-use web_sys::HtmlImageElement;
+use anyhow::{anyhow, Result};
+use rand::{distributions::WeightedIndex, prelude::*};
+use serde::Deserialize;
+use std::{collections::HashMap, rc::Rc};
 
-use crate::engine::{Image, Point, Rect, SpriteSheet};
+use crate::engine::{Image, ImageHandle, Point, Rect, SheetRect, SpriteSheet};
 use crate::game::{Barrier, Obstacle, Platform};
 
-// const LOW_PLATFORM: i16 = 420;
-const HIGH_PLATFORM: i16 = 375;
-const FIRST_PLATFORM: i16 = 370;
-
-const STONE_ON_GROUND: i16 = 546;
-
-const FLOATING_PLATFORM_SPRITES: [&str; 3] = ["13.png", "14.png", "15.png"];
-const PLATFORM_WIDTH: i16 = 384;
-const PLATFORM_HEIGHT: i16 = 93;
-const PLATFORM_EDGE_WIDTH: i16 = 60;
-const PLATFORM_EDGE_HEIGHT: i16 = 54;
-const FLOATING_PLATFORM_BOUNDING_BOXES: [Rect; 3] = [
-    Rect::new_from_x_y(0, 0, PLATFORM_EDGE_WIDTH, PLATFORM_EDGE_HEIGHT),
-    Rect::new_from_x_y(
-        PLATFORM_EDGE_WIDTH,
-        0,
-        PLATFORM_WIDTH - (PLATFORM_EDGE_WIDTH * 2),
-        PLATFORM_HEIGHT,
-    ),
-    Rect::new_from_x_y(
-        PLATFORM_WIDTH - PLATFORM_EDGE_WIDTH,
-        0,
-        PLATFORM_EDGE_WIDTH,
-        PLATFORM_EDGE_HEIGHT,
-    ),
-];
-
-fn create_floating_platform(sprite_sheet: Rc<SpriteSheet>, position: Point) -> Platform {
-    Platform::new(
-        sprite_sheet,
-        position,
-        &FLOATING_PLATFORM_SPRITES,
-        &FLOATING_PLATFORM_BOUNDING_BOXES,
-    )
+// Offsets are relative to the start of the segment, bounding boxes to the platform's position.
+#[derive(Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ObstacleDefinition {
+    Barrier {
+        image: String,
+        x: i16,
+        y: i16,
+    },
+    Platform {
+        sprites: Vec<String>,
+        x: i16,
+        y: i16,
+        bounding_boxes: Vec<SheetRect>,
+    },
 }
 
-pub fn stone_and_platform(
-    stone: HtmlImageElement,
-    sprite_sheet: Rc<SpriteSheet>,
-    offset_x: i16,
-) -> Vec<Box<dyn Obstacle>> {
-    const INITIAL_STONE_OFFSET: i16 = 150;
-
-    vec![
-        Box::new(Barrier::new(Image::new(
-            stone,
-            Point {
-                x: offset_x + INITIAL_STONE_OFFSET,
-                y: STONE_ON_GROUND,
-            },
-        ))),
-        Box::new(create_floating_platform(
-            sprite_sheet,
-            Point {
-                x: offset_x + FIRST_PLATFORM,
-                y: HIGH_PLATFORM,
-            },
-        )),
-    ]
+// Distance, in the score's units, the boy has to cover before harder segments get more likely.
+const DISTANCE_PER_LEVEL: u32 = 100;
+const MAX_LEVEL: u32 = 10;
+// Keeps the scaled weights of a whole catalogue well within a u32 at the highest level.
+const MAX_WEIGHT: u32 = 1000;
+const MAX_DIFFICULTY: u32 = 100;
+
+#[derive(Deserialize, Clone)]
+pub struct SegmentDefinition {
+    pub name: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    // 0 never gets more likely, every point above that adds the base weight again per level.
+    #[serde(default)]
+    pub difficulty: u32,
+    pub obstacles: Vec<ObstacleDefinition>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SegmentCatalogue {
+    pub starting_segment: String,
+    #[serde(default = "default_max_repeats")]
+    pub max_repeats: usize,
+    pub segments: Vec<SegmentDefinition>,
+}
+
+fn default_max_repeats() -> usize {
+    2
+}
+
+impl SegmentCatalogue {
+    // Every barrier image the catalogue refers to, so they can be loaded up front.
+    pub fn barrier_images(&self) -> Vec<String> {
+        let mut images: Vec<String> = self
+            .segments
+            .iter()
+            .flat_map(|segment| segment.obstacles.iter())
+            .filter_map(|obstacle| match obstacle {
+                ObstacleDefinition::Barrier { image, .. } => Some(image.clone()),
+                ObstacleDefinition::Platform { .. } => None,
+            })
+            .collect();
+        images.sort();
+        images.dedup();
+        images
+    }
+}
+
+pub struct SegmentSelector {
+    max_repeats: usize,
+    last: Option<usize>,
+    repeats: usize,
 }
 
-pub fn platform_and_stone(
-    stone: HtmlImageElement,
+impl SegmentSelector {
+    // Picks by weight, scaled up for harder segments the further the boy has walked.
+    // A segment that has already come up max_repeats times in a row is left out, unless
+    // it is the only one left with any weight. Only recorded picks count as having come up.
+    pub fn select(
+        &self,
+        segments: &[SegmentDefinition],
+        distance: u32,
+        rng: &mut impl Rng,
+    ) -> usize {
+        let level = (distance / DISTANCE_PER_LEVEL).min(MAX_LEVEL);
+        let weights = segments.iter().enumerate().map(|(index, segment)| {
+            if self.last == Some(index) && self.repeats >= self.max_repeats {
+                0
+            } else {
+                segment.weight * (1 + segment.difficulty * level)
+            }
+        });
+
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => distribution.sample(rng),
+            Err(_) => self.last.unwrap_or(0),
+        }
+    }
+
+    // The segment that was actually placed, whether it was picked or not.
+    pub fn record(&mut self, index: usize) {
+        if self.last == Some(index) {
+            self.repeats += 1;
+        } else {
+            self.last = Some(index);
+            self.repeats = 1;
+        }
+    }
+}
+
+// The loaded catalogue together with the images its obstacles are drawn with.
+pub struct Segments {
+    catalogue: SegmentCatalogue,
+    barrier_images: HashMap<String, ImageHandle>,
     sprite_sheet: Rc<SpriteSheet>,
-    offset_x: i16,
-) -> Vec<Box<dyn Obstacle>> {
-    const INITIAL_STONE_OFFSET: i16 = 400;
-    const INITIAL_PLATFORM_OFFSET: i16 = 200;
-
-    vec![
-        Box::new(Barrier::new(Image::new(
-            stone,
-            Point {
-                x: offset_x + INITIAL_STONE_OFFSET,
-                y: STONE_ON_GROUND,
-            },
-        ))),
-        Box::new(create_floating_platform(
+    starting_segment: usize,
+}
+
+impl Segments {
+    pub fn new(
+        catalogue: SegmentCatalogue,
+        barrier_images: HashMap<String, ImageHandle>,
+        sprite_sheet: Rc<SpriteSheet>,
+    ) -> Result<Self> {
+        let starting_segment = catalogue
+            .segments
+            .iter()
+            .position(|segment| segment.name == catalogue.starting_segment)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Starting segment {} is not in the catalogue",
+                    catalogue.starting_segment
+                )
+            })?;
+
+        if let Some(segment) = catalogue
+            .segments
+            .iter()
+            .find(|segment| segment.weight > MAX_WEIGHT || segment.difficulty > MAX_DIFFICULTY)
+        {
+            return Err(anyhow!(
+                "Segment {} is over the maximum weight of {} or difficulty of {}",
+                segment.name,
+                MAX_WEIGHT,
+                MAX_DIFFICULTY
+            ));
+        }
+
+        if catalogue.segments.iter().all(|segment| segment.weight == 0) {
+            return Err(anyhow!("Every segment in the catalogue has a weight of 0"));
+        }
+
+        if let Some(missing) = catalogue
+            .barrier_images()
+            .into_iter()
+            .find(|image| !barrier_images.contains_key(image))
+        {
+            return Err(anyhow!("No image loaded for barrier {}", missing));
+        }
+
+        // rightmost and Platform::right need at least one of each
+        if let Some(empty) = catalogue
+            .segments
+            .iter()
+            .find(|segment| segment.obstacles.is_empty())
+        {
+            return Err(anyhow!("Segment {} has no obstacles", empty.name));
+        }
+
+        if let Some(segment) = catalogue.segments.iter().find(|segment| {
+            segment.obstacles.iter().any(|obstacle| match obstacle {
+                ObstacleDefinition::Platform { bounding_boxes, .. } => bounding_boxes.is_empty(),
+                ObstacleDefinition::Barrier { .. } => false,
+            })
+        }) {
+            return Err(anyhow!(
+                "Segment {} has a platform without bounding boxes",
+                segment.name
+            ));
+        }
+
+        if let Some((segment, sprite)) = catalogue.segments.iter().find_map(|segment| {
+            segment
+                .obstacles
+                .iter()
+                .find_map(|obstacle| match obstacle {
+                    ObstacleDefinition::Platform { sprites, .. } => sprites
+                        .iter()
+                        .find(|sprite| sprite_sheet.cell(sprite).is_none()),
+                    ObstacleDefinition::Barrier { .. } => None,
+                })
+                .map(|sprite| (segment, sprite))
+        }) {
+            return Err(anyhow!(
+                "Segment {} has a platform sprite {} that isn't on the tile sheet",
+                segment.name,
+                sprite
+            ));
+        }
+
+        Ok(Segments {
+            catalogue,
+            barrier_images,
             sprite_sheet,
-            Point {
-                x: offset_x + INITIAL_PLATFORM_OFFSET,
-                y: HIGH_PLATFORM,
-            },
-        )),
-    ]
+            starting_segment,
+        })
+    }
+
+    pub fn name(&self, index: usize) -> &str {
+        &self.catalogue.segments[index].name
+    }
+
+    // A selector that counts the starting segment as the first one in a row.
+    pub fn selector(&self) -> SegmentSelector {
+        SegmentSelector {
+            max_repeats: self.catalogue.max_repeats,
+            last: Some(self.starting_segment),
+            repeats: 1,
+        }
+    }
+
+    pub fn select(&self, selector: &SegmentSelector, distance: u32, rng: &mut StdRng) -> usize {
+        selector.select(&self.catalogue.segments, distance, rng)
+    }
+
+    pub fn starting_index(&self) -> usize {
+        self.starting_segment
+    }
+
+    pub fn starting_segment(&self, offset_x: i16) -> Vec<Box<dyn Obstacle>> {
+        self.create(self.starting_segment, offset_x)
+    }
+
+    pub fn create(&self, index: usize, offset_x: i16) -> Vec<Box<dyn Obstacle>> {
+        self.catalogue.segments[index]
+            .obstacles
+            .iter()
+            .map(|obstacle| self.create_obstacle(obstacle, offset_x))
+            .collect()
+    }
+
+    fn create_obstacle(&self, obstacle: &ObstacleDefinition, offset_x: i16) -> Box<dyn Obstacle> {
+        match obstacle {
+            ObstacleDefinition::Barrier { image, x, y } => Box::new(Barrier::new(Image::new(
+                self.barrier_images[image],
+                Point {
+                    x: offset_x + x,
+                    y: *y,
+                },
+            ))),
+            ObstacleDefinition::Platform {
+                sprites,
+                x,
+                y,
+                bounding_boxes,
+            } => {
+                let sprite_names: Vec<&str> = sprites.iter().map(|name| name.as_str()).collect();
+                let bounding_boxes: Vec<Rect> = bounding_boxes
+                    .iter()
+                    .map(|bb| Rect::new_from_x_y(bb.x, bb.y, bb.w, bb.h))
+                    .collect();
+
+                Box::new(Platform::new(
+                    self.sprite_sheet.clone(),
+                    Point {
+                        x: offset_x + x,
+                        y: *y,
+                    },
+                    &sprite_names,
+                    &bounding_boxes,
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Cell, Sheet};
+    use crate::reachability::is_reachable;
+
+    fn shipped_catalogue() -> SegmentCatalogue {
+        serde_json::from_str(include_str!("../static/segments.json")).unwrap()
+    }
+
+    // Has every sprite the shipped platforms are drawn with.
+    fn tile_sheet() -> Rc<SpriteSheet> {
+        let tiles = Sheet {
+            frames: ["13.png", "14.png", "15.png"]
+                .iter()
+                .map(|name| {
+                    let frame = SheetRect {
+                        x: 0,
+                        y: 0,
+                        w: 128,
+                        h: 93,
+                    };
+                    (
+                        name.to_string(),
+                        Cell {
+                            frame: frame.clone(),
+                            sprite_source_size: frame,
+                        },
+                    )
+                })
+                .collect(),
+        };
+        Rc::new(SpriteSheet::new(ImageHandle::new(0, 1024, 1024), tiles))
+    }
+
+    fn segment(weight: u32, difficulty: u32) -> SegmentDefinition {
+        SegmentDefinition {
+            name: format!("{}_{}", weight, difficulty),
+            weight,
+            difficulty,
+            obstacles: vec![],
+        }
+    }
+
+    fn selector(max_repeats: usize) -> SegmentSelector {
+        SegmentSelector {
+            max_repeats,
+            last: None,
+            repeats: 0,
+        }
+    }
+
+    fn picks(
+        selector: &mut SegmentSelector,
+        segments: &[SegmentDefinition],
+        distance: u32,
+    ) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..1000)
+            .map(|_| {
+                let next = selector.select(segments, distance, &mut rng);
+                selector.record(next);
+                next
+            })
+            .collect()
+    }
+
+    #[test]
+    fn selector_never_repeats_a_segment_more_than_max_repeats_in_a_row() {
+        let segments = [segment(100, 0), segment(1, 0)];
+        let picks = picks(&mut selector(2), &segments, 0);
+
+        assert!(picks
+            .windows(3)
+            .all(|run| !(run[0] == run[1] && run[1] == run[2])));
+        assert!(picks.contains(&1));
+    }
+
+    #[test]
+    fn only_recorded_segments_count_towards_max_repeats() {
+        let segments = [segment(100, 0), segment(1, 0)];
+        let mut selector = selector(2);
+        selector.record(0);
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let picks = (0..20)
+            .filter(|_| selector.select(&segments, 0, &mut rng) == 0)
+            .count();
+        assert!(picks > 15);
+
+        selector.record(0);
+        assert!((0..20).all(|_| selector.select(&segments, 0, &mut rng) == 1));
+    }
+
+    #[test]
+    fn selector_favours_harder_segments_further_along() {
+        let segments = [segment(3, 0), segment(1, 2)];
+        let hard_count = |distance| {
+            picks(&mut selector(1000), &segments, distance)
+                .iter()
+                .filter(|&&index| index == 1)
+                .count()
+        };
+
+        assert!(hard_count(0) < 350);
+        assert!(hard_count(DISTANCE_PER_LEVEL * MAX_LEVEL) > 800);
+    }
+
+    #[test]
+    fn every_shipped_segment_can_be_walked_through() {
+        let segments = Segments::new(
+            shipped_catalogue(),
+            HashMap::from([("Stone.png".to_string(), ImageHandle::new(1, 50, 54))]),
+            tile_sheet(),
+        )
+        .unwrap();
+
+        for index in 0..segments.catalogue.segments.len() {
+            assert!(
+                is_reachable(&segments.create(index, 0), 70),
+                "{} can't be walked through",
+                segments.name(index)
+            );
+        }
+    }
+
+    #[test]
+    fn segments_require_every_barrier_image_and_the_starting_segment() {
+        let stone = HashMap::from([("Stone.png".to_string(), ImageHandle::new(1, 50, 54))]);
+        assert!(Segments::new(shipped_catalogue(), stone.clone(), tile_sheet()).is_ok());
+        assert!(Segments::new(shipped_catalogue(), HashMap::new(), tile_sheet()).is_err());
+
+        let mut catalogue = shipped_catalogue();
+        catalogue.starting_segment = "missing".into();
+        assert!(Segments::new(catalogue, stone, tile_sheet()).is_err());
+    }
+
+    #[test]
+    fn weights_and_difficulties_too_big_to_scale_are_rejected() {
+        let stone = HashMap::from([("Stone.png".to_string(), ImageHandle::new(1, 50, 54))]);
+        let mut catalogue = shipped_catalogue();
+        catalogue.segments[0].weight = MAX_WEIGHT;
+        catalogue.segments[0].difficulty = MAX_DIFFICULTY;
+        assert!(Segments::new(catalogue.clone(), stone.clone(), tile_sheet()).is_ok());
+
+        catalogue.segments[0].weight = u32::MAX;
+        assert!(Segments::new(catalogue.clone(), stone.clone(), tile_sheet()).is_err());
+
+        catalogue.segments[0].weight = 1;
+        catalogue.segments[0].difficulty = u32::MAX;
+        assert!(Segments::new(catalogue, stone, tile_sheet()).is_err());
+    }
+
+    #[test]
+    fn segments_without_obstacles_are_rejected() {
+        let stone = HashMap::from([("Stone.png".to_string(), ImageHandle::new(1, 50, 54))]);
+        let mut catalogue = shipped_catalogue();
+        catalogue.segments[0].obstacles.clear();
+
+        assert!(Segments::new(catalogue, stone, tile_sheet()).is_err());
+    }
+
+    #[test]
+    fn platforms_without_bounding_boxes_are_rejected() {
+        let stone = HashMap::from([("Stone.png".to_string(), ImageHandle::new(1, 50, 54))]);
+        let mut catalogue = shipped_catalogue();
+        let platform = catalogue
+            .segments
+            .iter_mut()
+            .flat_map(|segment| segment.obstacles.iter_mut())
+            .find_map(|obstacle| match obstacle {
+                ObstacleDefinition::Platform { bounding_boxes, .. } => Some(bounding_boxes),
+                ObstacleDefinition::Barrier { .. } => None,
+            })
+            .unwrap();
+        platform.clear();
+
+        assert!(Segments::new(catalogue, stone, tile_sheet()).is_err());
+    }
+
+    #[test]
+    fn platform_sprites_missing_from_the_tile_sheet_are_rejected() {
+        let stone = HashMap::from([("Stone.png".to_string(), ImageHandle::new(1, 50, 54))]);
+        let mut catalogue = shipped_catalogue();
+        let (name, sprites) = catalogue
+            .segments
+            .iter_mut()
+            .find_map(|segment| {
+                let name = segment.name.clone();
+                segment
+                    .obstacles
+                    .iter_mut()
+                    .find_map(|obstacle| match obstacle {
+                        ObstacleDefinition::Platform { sprites, .. } => {
+                            Some((name.clone(), sprites))
+                        }
+                        ObstacleDefinition::Barrier { .. } => None,
+                    })
+            })
+            .unwrap();
+        sprites.push("missing.png".into());
+
+        let err = Segments::new(catalogue, stone, tile_sheet())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            err.contains(&name) && err.contains("missing.png"),
+            "{}",
+            err
+        );
+    }
 }
@@ -0,0 +1,345 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    rc::Rc,
+};
+
+use crate::engine::{AudioSpriteSheet, Bus, PlaybackHandle, SoundHandle, SoundPlayer};
+use crate::state::GameEvent;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum SoundSource {
+    File { file: String },
+    // One of the sprites on the manifest's sprite sheet
+    Sprite { sprite: String },
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SoundDefinition {
+    #[serde(flatten)]
+    pub source: SoundSource,
+    // How many of it can play at once, at least 1. One more cuts off the oldest.
+    #[serde(default = "default_polyphony")]
+    pub polyphony: usize,
+    // Relative to the rest of the bus, from 0 to 1
+    #[serde(default = "default_volume")]
+    pub volume: f32,
+}
+
+fn default_polyphony() -> usize {
+    1
+}
+
+fn default_volume() -> f32 {
+    1.0
+}
+
+// The sound effects by name, and the one each event plays. Events left out are silent.
+#[derive(Deserialize, Clone)]
+pub struct SoundManifest {
+    #[serde(default)]
+    pub sprite_sheet: Option<AudioSpriteSheet>,
+    pub sounds: HashMap<String, SoundDefinition>,
+    #[serde(default)]
+    pub events: HashMap<GameEvent, String>,
+}
+
+impl SoundManifest {
+    // Every file a sound plays from in full, once each however many sounds share it.
+    pub fn files(&self) -> HashSet<&str> {
+        self.sounds
+            .values()
+            .filter_map(|definition| match &definition.source {
+                SoundSource::File { file } => Some(file.as_str()),
+                SoundSource::Sprite { .. } => None,
+            })
+            .collect()
+    }
+
+    // The handle for each sound, from the files and the sprite sheet's sprites as loaded.
+    pub fn resolve(
+        &self,
+        files: &HashMap<String, SoundHandle>,
+        sprites: &HashMap<String, SoundHandle>,
+    ) -> Result<HashMap<String, SoundHandle>> {
+        self.sounds
+            .iter()
+            .map(|(name, definition)| {
+                let handle = match &definition.source {
+                    SoundSource::File { file } => files
+                        .get(file)
+                        .ok_or_else(|| anyhow!("No sound loaded from {}", file))?,
+                    SoundSource::Sprite { sprite } => sprites
+                        .get(sprite)
+                        .ok_or_else(|| anyhow!("No sprite {} on the sound sprite sheet", sprite))?,
+                };
+                Ok((name.clone(), *handle))
+            })
+            .collect()
+    }
+}
+
+struct Voices {
+    sound: SoundHandle,
+    polyphony: usize,
+    volume: f32,
+    // Oldest first
+    playing: VecDeque<PlaybackHandle>,
+}
+
+// Everything the walk plays: the music, and a sound effect for each game event.
+pub struct SoundManager {
+    audio: Rc<dyn SoundPlayer>,
+    music: PlaybackHandle,
+    voices: HashMap<String, Voices>,
+    events: HashMap<GameEvent, String>,
+}
+
+impl SoundManager {
+    pub fn new(
+        audio: Rc<dyn SoundPlayer>,
+        music: PlaybackHandle,
+        manifest: SoundManifest,
+        sounds: HashMap<String, SoundHandle>,
+    ) -> Result<Self> {
+        if let Some((event, missing)) = manifest
+            .events
+            .iter()
+            .find(|(_, name)| !manifest.sounds.contains_key(*name))
+        {
+            return Err(anyhow!(
+                "Sound {} for {:?} is not in the manifest",
+                missing,
+                event
+            ));
+        }
+
+        if let Some((name, _)) = manifest
+            .sounds
+            .iter()
+            .find(|(_, definition)| definition.polyphony == 0)
+        {
+            return Err(anyhow!("Sound {} has a polyphony of 0", name));
+        }
+
+        let voices = manifest
+            .sounds
+            .into_iter()
+            .map(|(name, definition)| {
+                let sound = *sounds
+                    .get(&name)
+                    .ok_or_else(|| anyhow!("No sound loaded for {}", name))?;
+                let voices = Voices {
+                    sound,
+                    polyphony: definition.polyphony,
+                    volume: definition.volume,
+                    playing: VecDeque::new(),
+                };
+                Ok((name, voices))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(SoundManager {
+            audio,
+            music,
+            voices,
+            events: manifest.events,
+        })
+    }
+
+    pub fn audio(&self) -> &Rc<dyn SoundPlayer> {
+        &self.audio
+    }
+
+    pub fn music(&self) -> &PlaybackHandle {
+        &self.music
+    }
+
+    pub fn play(&mut self, event: GameEvent) -> Result<()> {
+        let voices = match self.events.get(&event) {
+            Some(name) => self
+                .voices
+                .get_mut(name)
+                .ok_or_else(|| anyhow!("No sound loaded for {}", name))?,
+            None => return Ok(()),
+        };
+
+        voices.playing.retain(|playback| !playback.has_ended());
+        if voices.playing.len() >= voices.polyphony {
+            if let Some(oldest) = voices.playing.pop_front() {
+                oldest.stop()?;
+            }
+        }
+
+        let playback = self.audio.play_sound(&voices.sound, Bus::Sfx)?;
+        playback.set_volume(voices.volume)?;
+        voices.playing.push_back(playback);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{HeadlessAudio, PlaybackStatus};
+
+    fn manifest(json: &str) -> SoundManifest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn new_manager(audio: Rc<HeadlessAudio>, manifest: SoundManifest) -> Result<SoundManager> {
+        let music = audio
+            .play_looping_sound(&SoundHandle::new(1), Bus::Music)
+            .unwrap();
+        let sounds = HashMap::from([("jump".to_string(), SoundHandle::new(0))]);
+        SoundManager::new(audio, music, manifest, sounds)
+    }
+
+    #[test]
+    fn shipped_manifest_plays_the_jump_sound_for_jumps_only() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let mut sounds = new_manager(
+            audio.clone(),
+            manifest(include_str!("../static/sounds.json")),
+        )
+        .unwrap();
+
+        sounds.play(GameEvent::Jumped).unwrap();
+        sounds.play(GameEvent::Landed).unwrap();
+
+        assert_eq!(audio.played_on(Bus::Sfx), vec![SoundHandle::new(0)]);
+    }
+
+    #[test]
+    fn playing_past_the_polyphony_cuts_off_the_oldest() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let mut sounds = new_manager(
+            audio.clone(),
+            manifest(
+                r#"{
+                    "sounds": { "jump": { "file": "jump.mp3", "polyphony": 2 } },
+                    "events": { "jumped": "jump", "scored": "jump" }
+                }"#,
+            ),
+        )
+        .unwrap();
+
+        sounds.play(GameEvent::Jumped).unwrap();
+        sounds.play(GameEvent::Scored).unwrap();
+        sounds.play(GameEvent::Jumped).unwrap();
+
+        let statuses: Vec<PlaybackStatus> = audio
+            .playbacks()
+            .iter()
+            .filter(|playback| playback.bus == Bus::Sfx)
+            .map(|playback| playback.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                PlaybackStatus::Stopped,
+                PlaybackStatus::Playing,
+                PlaybackStatus::Playing
+            ]
+        );
+    }
+
+    #[test]
+    fn sounds_play_at_their_own_volume() {
+        let audio = Rc::new(HeadlessAudio::new());
+        let mut sounds = new_manager(
+            audio.clone(),
+            manifest(
+                r#"{
+                    "sounds": { "jump": { "file": "jump.mp3", "volume": 0.4 } },
+                    "events": { "jumped": "jump" }
+                }"#,
+            ),
+        )
+        .unwrap();
+
+        sounds.play(GameEvent::Jumped).unwrap();
+
+        let jump = audio.playbacks()[1];
+        assert_eq!(jump.bus, Bus::Sfx);
+        assert!((jump.volume - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sounds_come_from_their_own_file_or_a_sprite() {
+        let manifest = manifest(
+            r#"{
+                "sprite_sheet": {
+                    "file": "sfx.mp3",
+                    "sprites": { "coin": { "offset": 1.5, "duration": 0.25 } }
+                },
+                "sounds": {
+                    "jump": { "file": "jump.mp3" },
+                    "coin": { "sprite": "coin", "polyphony": 4 }
+                }
+            }"#,
+        );
+
+        assert_eq!(
+            manifest.sounds["jump"].source,
+            SoundSource::File {
+                file: "jump.mp3".to_string()
+            }
+        );
+        assert_eq!(
+            manifest.sounds["coin"].source,
+            SoundSource::Sprite {
+                sprite: "coin".to_string()
+            }
+        );
+        assert_eq!(manifest.sounds["coin"].polyphony, 4);
+        assert_eq!(
+            manifest.sprite_sheet.as_ref().unwrap().sprites["coin"].duration,
+            Some(0.25)
+        );
+        assert_eq!(manifest.files(), HashSet::from(["jump.mp3"]));
+
+        let sounds = manifest
+            .resolve(
+                &HashMap::from([("jump.mp3".to_string(), SoundHandle::new(0))]),
+                &HashMap::from([("coin".to_string(), SoundHandle::new(1))]),
+            )
+            .unwrap();
+        assert_eq!(sounds["jump"], SoundHandle::new(0));
+        assert_eq!(sounds["coin"], SoundHandle::new(1));
+    }
+
+    #[test]
+    fn sounds_from_sprites_missing_from_the_sheet_are_rejected() {
+        let manifest = manifest(
+            r#"{
+                "sprite_sheet": { "file": "sfx.mp3", "sprites": {} },
+                "sounds": { "coin": { "sprite": "coin" } }
+            }"#,
+        );
+
+        assert!(manifest.resolve(&HashMap::new(), &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn events_for_sounds_missing_from_the_manifest_are_rejected() {
+        let result = new_manager(
+            Rc::new(HeadlessAudio::new()),
+            manifest(r#"{ "sounds": {}, "events": { "landed": "thud" } }"#),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn sounds_that_could_never_play_are_rejected() {
+        let result = new_manager(
+            Rc::new(HeadlessAudio::new()),
+            manifest(r#"{ "sounds": { "jump": { "file": "jump.mp3", "polyphony": 0 } } }"#),
+        );
+
+        assert!(result.is_err());
+    }
+}
@@ -1,10 +1,22 @@
 use anyhow::{anyhow, Result};
 use js_sys::ArrayBuffer;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{AudioBuffer, AudioBufferSourceNode, AudioContext, AudioNode};
+use web_sys::{
+    AudioBuffer, AudioBufferSourceNode, AudioContext, AudioContextState, AudioNode, AudioParam,
+    GainNode,
+};
 
 use crate::browser;
+use crate::engine::{
+    AudioSprite, AudioSpriteSheet, AudioState, Bus, Fade, MixerSettings, Playback, PlaybackHandle,
+    SoundHandle, SoundPlayer,
+};
 
 pub fn create_audio_context() -> Result<AudioContext> {
     AudioContext::new().map_err(|err| anyhow!("Failed to create audio context: {:#?}", err))
@@ -24,41 +36,238 @@ fn connect_with_audio_node(
         .map_err(|err| anyhow!("Failed to connect with audio node: {:#?}", err))
 }
 
-fn create_track_source(ctx: &AudioContext, buffer: &AudioBuffer, volume: f32) -> Result<AudioBufferSourceNode> {
-    let track_source = create_buffer_source(ctx)?;
-    track_source.set_buffer(Some(&buffer));
-
-    // adjust volume
+fn create_gain_node(ctx: &AudioContext, destination: &AudioNode) -> Result<GainNode> {
     let gain_node = ctx
         .create_gain()
         .map_err(|err| anyhow!("Failed to create gain node: {:#?}", err))?;
+    gain_node
+        .connect_with_audio_node(destination)
+        .map_err(|err| anyhow!("Failed to connect gain node: {:#?}", err))?;
+
+    Ok(gain_node)
+}
+
+fn set_gain(ctx: &AudioContext, gain_node: &GainNode, volume: f32) -> Result<()> {
     gain_node
         .gain()
         .set_value_at_time(volume, ctx.current_time())
-        .map_err(|err| anyhow!("Failed to set gain value at time: {:#?}", err))?;
+        .map(|_| ())
+        .map_err(|err| anyhow!("Failed to set gain value at time: {:#?}", err))
+}
 
-    connect_with_audio_node(&track_source, &gain_node)?;
-    gain_node
-        .connect_with_audio_node(&ctx.destination())
-        .map_err(|err| anyhow!("Failed to set gain value at time: {:#?}", err))?;
+fn cancel_scheduled_values(param: &AudioParam, time: f64) -> Result<()> {
+    param
+        .cancel_scheduled_values(time)
+        .map(|_| ())
+        .map_err(|err| anyhow!("Failed to cancel scheduled values: {:#?}", err))
+}
+
+// Holds the param at the value it has at `time`, even halfway through a ramp. web-sys doesn't bind
+// cancelAndHoldAtTime and not every browser has it, so false means it wasn't there to call.
+fn cancel_and_hold_at_time(param: &AudioParam, time: f64) -> Result<bool> {
+    let method = js_sys::Reflect::get(param, &"cancelAndHoldAtTime".into())
+        .map_err(|err| anyhow!("Failed to look up cancelAndHoldAtTime: {:#?}", err))?;
+    match method.dyn_into::<js_sys::Function>() {
+        Ok(method) => method
+            .call1(param, &time.into())
+            .map(|_| true)
+            .map_err(|err| anyhow!("Failed to cancel and hold: {:#?}", err)),
+        Err(_) => Ok(false),
+    }
+}
+
+fn create_track_source(
+    ctx: &AudioContext,
+    buffer: &AudioBuffer,
+    destination: &AudioNode,
+) -> Result<AudioBufferSourceNode> {
+    let track_source = create_buffer_source(ctx)?;
+    track_source.set_buffer(Some(&buffer));
+    connect_with_audio_node(&track_source, destination)?;
 
     Ok(track_source)
 }
 
+#[derive(Clone, Copy)]
 pub enum LOOPING {
     NO,
     YES,
 }
 
-pub fn play_sound(ctx: &AudioContext, buffer: &AudioBuffer, looping: LOOPING, volume: f32) -> Result<()> {
-    let source = create_track_source(ctx, buffer, volume)?;
-    if matches!(looping, LOOPING::YES) {
-        source.set_loop(true);
+// Plays only the sprite's stretch of the buffer, starting `offset` seconds into it.
+pub fn play_sound(
+    ctx: &AudioContext,
+    buffer: &AudioBuffer,
+    sprite: &AudioSprite,
+    looping: LOOPING,
+    destination: &AudioNode,
+    offset: f64,
+) -> Result<AudioBufferSourceNode> {
+    let source = create_track_source(ctx, buffer, destination)?;
+    let start = sprite.offset + offset;
+
+    match looping {
+        LOOPING::YES => {
+            source.set_loop(true);
+            source.set_loop_start(sprite.offset);
+            source.set_loop_end(sprite.offset + sprite.duration);
+            source.start_with_when_and_grain_offset(0.0, start)
+        }
+        LOOPING::NO => source.start_with_when_and_grain_offset_and_grain_duration(
+            0.0,
+            start,
+            sprite.duration - offset,
+        ),
     }
+    .map_err(|err| anyhow!("Failed to start sound: {:#?}", err))?;
+    Ok(source)
+}
 
-    source
-        .start()
-        .map_err(|err| anyhow!("Failed to start sound: {:#?}", err))
+// Exponential ramps can't reach 0, this is as close as they go before dropping to silence.
+const MIN_EXPONENTIAL_VOLUME: f32 = 0.0001;
+
+// A source node can only be started once, so pausing stops it and resuming starts
+// a new one where it left off. Every playback has its own gain node in front of its bus,
+// disconnected once the playback is over.
+struct WebPlayback {
+    // For the sources' ended callbacks, which mustn't keep the playback alive
+    this: Weak<WebPlayback>,
+    context: AudioContext,
+    buffer: AudioBuffer,
+    sprite: AudioSprite,
+    looping: LOOPING,
+    gain: GainNode,
+    source: RefCell<Option<AudioBufferSourceNode>>,
+    // Context time the buffer would have started at, playing without a pause
+    started_at: Cell<f64>,
+    paused_at: Cell<Option<f64>>,
+}
+
+impl WebPlayback {
+    fn start(&self, offset: f64) -> Result<()> {
+        let source = play_sound(
+            &self.context,
+            &self.buffer,
+            &self.sprite,
+            self.looping,
+            &self.gain,
+            offset,
+        )?;
+        let playback = self.this.clone();
+        let gain = self.gain.clone();
+        let ended = source.clone();
+        let onended = browser::closure_once_into_js(move || match playback.upgrade() {
+            Some(playback) => playback.source_ended(&ended),
+            // nothing can play it again
+            None => {
+                if let Err(err) = gain.disconnect() {
+                    error!("Failed to disconnect sound: {:#?}", err);
+                }
+            }
+        });
+        source.set_onended(Some(onended.unchecked_ref()));
+
+        self.started_at.set(self.context.current_time() - offset);
+        self.source.replace(Some(source));
+        Ok(())
+    }
+
+    // Pausing, or resuming before the old source's end came through, doesn't end the playback.
+    fn source_ended(&self, ended: &AudioBufferSourceNode) {
+        let superseded = matches!(self.source.borrow().as_ref(), Some(source) if source != ended);
+        if self.paused_at.get().is_none() && !superseded {
+            self.disconnect();
+        }
+    }
+
+    fn disconnect(&self) {
+        if let Err(err) = self.gain.disconnect() {
+            error!("Failed to disconnect sound: {:#?}", err);
+        }
+    }
+
+    fn stop_source(&self) -> Result<()> {
+        match self.source.borrow_mut().take() {
+            Some(source) => source
+                .stop()
+                .map_err(|err| anyhow!("Failed to stop sound: {:#?}", err)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Playback for WebPlayback {
+    fn stop(&self) -> Result<()> {
+        self.paused_at.set(None);
+        if self.source.borrow().is_none() {
+            // paused or never started, there's no source left to end
+            self.disconnect();
+        }
+        self.stop_source()
+    }
+
+    fn pause(&self) -> Result<()> {
+        if self.source.borrow().is_none() {
+            return Ok(());
+        }
+
+        let played = self.context.current_time() - self.started_at.get();
+        let offset = match self.looping {
+            LOOPING::YES => played % self.sprite.duration,
+            LOOPING::NO => played.min(self.sprite.duration),
+        };
+        self.stop_source()?;
+        self.paused_at.set(Some(offset));
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<()> {
+        match self.paused_at.take() {
+            Some(offset) => self.start(offset),
+            None => Ok(()),
+        }
+    }
+
+    fn set_volume(&self, volume: f32) -> Result<()> {
+        cancel_scheduled_values(&self.gain.gain(), self.context.current_time())?;
+        set_gain(&self.context, &self.gain, volume)
+    }
+
+    fn fade_to(&self, volume: f32, seconds: f64, fade: Fade) -> Result<()> {
+        let param = self.gain.gain();
+        let now = self.context.current_time();
+        let end = now + seconds;
+
+        // ramps start from the last scheduled value, so pin it to where the fade starts from
+        if !cancel_and_hold_at_time(&param, now)? {
+            cancel_scheduled_values(&param, now)?;
+            set_gain(&self.context, &self.gain, param.value())?;
+        }
+
+        match fade {
+            Fade::Linear => param.linear_ramp_to_value_at_time(volume, end),
+            Fade::Exponential => param
+                .exponential_ramp_to_value_at_time(volume.max(MIN_EXPONENTIAL_VOLUME), end)
+                .and_then(|param| param.set_value_at_time(volume, end)),
+        }
+        .map(|_| ())
+        .map_err(|err| anyhow!("Failed to schedule fade: {:#?}", err))
+    }
+
+    fn has_ended(&self) -> bool {
+        // paused, or queued until the context is unlocked
+        if self.paused_at.get().is_some() {
+            return false;
+        }
+
+        match (self.source.borrow().as_ref(), self.looping) {
+            (None, _) => true,
+            (Some(_), LOOPING::YES) => false,
+            (Some(_), LOOPING::NO) => {
+                self.context.current_time() - self.started_at.get() >= self.sprite.duration
+            }
+        }
+    }
 }
 
 pub async fn decode_audio_data(
@@ -75,37 +284,299 @@ pub async fn decode_audio_data(
     .map_err(|err| anyhow!("Failed to cast audio buffer: {:#?}", err))
 }
 
-#[derive(Clone)]
-pub struct Audio {
-    context: AudioContext,
+// A gain node per bus, music and sfx feed the master one, which feeds the speakers.
+struct Buses {
+    master: GainNode,
+    music: GainNode,
+    sfx: GainNode,
 }
 
-#[derive(Clone)]
-pub struct Sound {
+impl Buses {
+    fn new(ctx: &AudioContext) -> Result<Self> {
+        let master = create_gain_node(ctx, &ctx.destination())?;
+        Ok(Buses {
+            music: create_gain_node(ctx, &master)?,
+            sfx: create_gain_node(ctx, &master)?,
+            master,
+        })
+    }
+
+    fn node(&self, bus: Bus) -> &GainNode {
+        match bus {
+            Bus::Master => &self.master,
+            Bus::Music => &self.music,
+            Bus::Sfx => &self.sfx,
+        }
+    }
+}
+
+// Any of these count as the gesture browsers wait for before letting audio play.
+const UNLOCK_EVENTS: [&str; 3] = ["keydown", "pointerdown", "touchend"];
+
+// A whole file is a sprite that covers all of its buffer, a sprite sheet's sprites share one.
+struct LoadedSound {
     buffer: AudioBuffer,
+    sprite: AudioSprite,
+}
+
+// Waits for a gesture the browser lets the context resume on, then starts what was played
+// before it. The listener only holds on to this weakly, so it goes with the last Audio.
+struct Unlock {
+    unlocked: Cell<bool>,
+    // Played while locked, started from the beginning once the context is unlocked
+    queued: RefCell<Vec<Rc<WebPlayback>>>,
+    listener: RefCell<Option<browser::ListenerClosure>>,
+}
+
+impl Unlock {
+    fn listen(self: &Rc<Self>, context: &AudioContext) -> Result<()> {
+        let unlock = Rc::downgrade(self);
+        let context = context.clone();
+        let listener = browser::closure_wrap(Box::new(move || {
+            if let Some(unlock) = unlock.upgrade() {
+                unlock.resume(&context);
+            }
+        }) as Box<dyn FnMut()>);
+
+        for event in UNLOCK_EVENTS.iter() {
+            browser::add_document_listener(event, &listener)?;
+        }
+        self.listener.replace(Some(listener));
+        Ok(())
+    }
+
+    // Has to run inside the gesture's event handler, browsers ignore a resume anywhere else.
+    // Not every gesture counts, and a resume on one that doesn't can stay pending for good,
+    // so every gesture asks again until the context is running.
+    fn resume(self: &Rc<Self>, context: &AudioContext) {
+        if self.unlocked.get() {
+            return;
+        }
+
+        let promise = match context.resume() {
+            Ok(promise) => promise,
+            Err(err) => {
+                error!("Failed to resume audio context: {:#?}", err);
+                return;
+            }
+        };
+        let unlock = Rc::downgrade(self);
+        let context = context.clone();
+        browser::spawn_local(async move {
+            if let Err(err) = JsFuture::from(promise).await {
+                error!("Audio is still locked: {:#?}", err);
+            }
+            match unlock.upgrade() {
+                Some(unlock) if context.state() == AudioContextState::Running => {
+                    if let Err(err) = unlock.unlocked() {
+                        error!("Error starting queued sounds: {:#?}", err);
+                    }
+                }
+                _ => {}
+            }
+        });
+    }
+
+    // Runs after the gesture's handler has returned, so the listener can be dropped here.
+    // Several resumes can get here, only the first one starts the queued sounds.
+    fn unlocked(&self) -> Result<()> {
+        if self.unlocked.replace(true) {
+            return Ok(());
+        }
+        self.stop_listening()?;
+        self.queued
+            .borrow_mut()
+            .drain(..)
+            .try_for_each(|playback| playback.resume())
+    }
+
+    fn stop_listening(&self) -> Result<()> {
+        if let Some(listener) = self.listener.borrow_mut().take() {
+            for event in UNLOCK_EVENTS.iter() {
+                browser::remove_document_listener(event, &listener)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Unlock {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop_listening() {
+            error!("Error removing the audio unlock listeners: {:#?}", err);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Audio {
+    context: AudioContext,
+    sounds: Rc<RefCell<Vec<LoadedSound>>>,
+    buses: Rc<Buses>,
+    mix: Rc<Cell<MixerSettings>>,
+    unlock: Rc<Unlock>,
 }
 
 impl Audio {
     pub fn new() -> Result<Self> {
-        Ok(Audio {
-            context: create_audio_context()?,
-        })
+        let context = create_audio_context()?;
+        let audio = Audio {
+            buses: Rc::new(Buses::new(&context)?),
+            unlock: Rc::new(Unlock {
+                unlocked: Cell::new(context.state() != AudioContextState::Suspended),
+                queued: RefCell::new(Vec::new()),
+                listener: RefCell::new(None),
+            }),
+            context,
+            sounds: Rc::new(RefCell::new(Vec::new())),
+            mix: Rc::new(Cell::new(MixerSettings::default())),
+        };
+        audio.set_mix(&MixerSettings::default())?;
+
+        if !audio.unlock.unlocked.get() {
+            audio.unlock.listen(&audio.context)?;
+        }
+
+        Ok(audio)
     }
 
-    pub async fn load_sound(&self, filename: &str) -> Result<Sound> {
+    async fn load_buffer(&self, filename: &str) -> Result<AudioBuffer> {
         let array_buffer = browser::fetch_array_buffer(filename).await?;
-        let audio_buffer = decode_audio_data(&self.context, &array_buffer).await?;
+        decode_audio_data(&self.context, &array_buffer).await
+    }
 
-        Ok(Sound {
-            buffer: audio_buffer,
-        })
+    fn add_sound(&self, buffer: AudioBuffer, sprite: AudioSprite) -> SoundHandle {
+        let mut sounds = self.sounds.borrow_mut();
+        sounds.push(LoadedSound { buffer, sprite });
+
+        SoundHandle::new(sounds.len() - 1)
+    }
+
+    pub async fn load_sound(&self, filename: &str) -> Result<SoundHandle> {
+        let buffer = self.load_buffer(filename).await?;
+        let sprite = AudioSprite {
+            offset: 0.0,
+            duration: buffer.duration(),
+        };
+
+        Ok(self.add_sound(buffer, sprite))
     }
 
-    pub fn play_sound(&self, sound: &Sound, volume: f32) -> Result<()> {
-        play_sound(&self.context, &sound.buffer, LOOPING::NO, volume)
+    // One fetch for the whole sheet, every sprite on it gets a handle of its own.
+    pub async fn load_sprite_sheet(
+        &self,
+        sheet: &AudioSpriteSheet,
+    ) -> Result<HashMap<String, SoundHandle>> {
+        let buffer = self.load_buffer(&sheet.file).await?;
+
+        sheet
+            .sprites
+            .iter()
+            .map(|(name, bounds)| {
+                let sprite = bounds
+                    .sprite(buffer.duration())
+                    .map_err(|err| anyhow!("Sprite {} on {} {}", name, sheet.file, err))?;
+                Ok((name.clone(), self.add_sound(buffer.clone(), sprite)))
+            })
+            .collect()
+    }
+
+    fn sound(&self, sound: &SoundHandle) -> Result<(AudioBuffer, AudioSprite)> {
+        self.sounds
+            .borrow()
+            .get(sound.id())
+            .map(|loaded| (loaded.buffer.clone(), loaded.sprite))
+            .ok_or_else(|| anyhow!("No sound loaded for {:?}", sound))
+    }
+
+    fn play(&self, sound: &SoundHandle, bus: Bus, looping: LOOPING) -> Result<PlaybackHandle> {
+        let (buffer, sprite) = self.sound(sound)?;
+        let gain = create_gain_node(&self.context, self.buses.node(bus))?;
+        let playback = Rc::new_cyclic(|this| WebPlayback {
+            this: this.clone(),
+            context: self.context.clone(),
+            buffer,
+            sprite,
+            looping,
+            gain,
+            source: RefCell::new(None),
+            started_at: Cell::new(0.0),
+            paused_at: Cell::new(None),
+        });
+
+        if self.unlock.unlocked.get() {
+            playback.start(0.0)?;
+        } else {
+            // waits as if paused at the start, unlocking resumes it unless it was stopped
+            playback.paused_at.set(Some(0.0));
+            self.unlock.queued.borrow_mut().push(playback.clone());
+        }
+
+        Ok(PlaybackHandle::new(playback))
+    }
+}
+
+impl SoundPlayer for Audio {
+    fn play_sound(&self, sound: &SoundHandle, bus: Bus) -> Result<PlaybackHandle> {
+        self.play(sound, bus, LOOPING::NO)
+    }
+
+    fn play_looping_sound(&self, sound: &SoundHandle, bus: Bus) -> Result<PlaybackHandle> {
+        self.play(sound, bus, LOOPING::YES)
+    }
+
+    fn suspend(&self) -> Result<()> {
+        self.context
+            .suspend()
+            .map(|_| ())
+            .map_err(|err| anyhow!("Failed to suspend audio context: {:#?}", err))
+    }
+
+    fn resume(&self) -> Result<()> {
+        if !self.unlock.unlocked.get() {
+            return Ok(());
+        }
+
+        self.context
+            .resume()
+            .map(|_| ())
+            .map_err(|err| anyhow!("Failed to resume audio context: {:#?}", err))
+    }
+
+    fn mix(&self) -> MixerSettings {
+        self.mix.get()
+    }
+
+    // Each bus node only gets its own level, the nodes multiply them on the way to master.
+    fn set_mix(&self, mix: &MixerSettings) -> Result<()> {
+        [Bus::Master, Bus::Music, Bus::Sfx]
+            .iter()
+            .try_for_each(|bus| {
+                set_gain(&self.context, self.buses.node(*bus), mix.bus(*bus).volume())
+            })?;
+        self.mix.set(*mix);
+
+        Ok(())
+    }
+
+    fn state(&self) -> AudioState {
+        if !self.unlock.unlocked.get() {
+            return AudioState::Locked;
+        }
+
+        match self.context.state() {
+            AudioContextState::Running => AudioState::Running,
+            AudioContextState::Closed => AudioState::Closed,
+            _ => AudioState::Suspended,
+        }
     }
 
-    pub fn play_looping_sound(&self, sound: &Sound, volume: f32) -> Result<()> {
-        play_sound(&self.context, &sound.buffer, LOOPING::YES, volume)
+    fn close(&self) -> Result<()> {
+        self.unlock.stop_listening()?;
+        self.unlock.queued.borrow_mut().clear();
+        self.context
+            .close()
+            .map(|_| ())
+            .map_err(|err| anyhow!("Failed to close audio context: {:#?}", err))
     }
 }
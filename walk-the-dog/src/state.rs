@@ -1,4 +1,5 @@
 use crate::state::red_hat_boy_states::*;
+use serde::Deserialize;
 
 #[derive(Clone)]
 pub enum RedHatBoyStateMachine {
@@ -19,6 +20,17 @@ pub enum Event {
     Land(i16),
 }
 
+// What happened in the game, for whatever wants to react to it, like the sound effects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameEvent {
+    Jumped,
+    Landed,
+    Slid,
+    KnockedOut,
+    Scored,
+}
+
 impl RedHatBoyStateMachine {
     pub fn transition(self, event: Event) -> Self {
         match (self.clone(), event) {
@@ -72,6 +84,22 @@ impl RedHatBoyStateMachine {
     pub fn update(self) -> Self {
         self.transition(Event::Update)
     }
+
+    // Everything the boy did since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        match self {
+            RedHatBoyStateMachine::Idle(state) => state.take_events(),
+            RedHatBoyStateMachine::Running(state) => state.take_events(),
+            RedHatBoyStateMachine::Jumping(state) => state.take_events(),
+            RedHatBoyStateMachine::Sliding(state) => state.take_events(),
+            RedHatBoyStateMachine::Falling(state) => state.take_events(),
+            RedHatBoyStateMachine::KnockedOut(state) => state.take_events(),
+        }
+    }
+
+    pub fn knocked_out(&self) -> bool {
+        matches!(self, RedHatBoyStateMachine::KnockedOut(_))
+    }
 }
 
 impl From<RedHatBoyState<Idle>> for RedHatBoyStateMachine {
@@ -140,25 +168,25 @@ impl From<FallingEndState> for RedHatBoyStateMachine {
 pub mod red_hat_boy_states {
     use crate::engine::Point;
     use crate::game::HEIGHT;
-    use crate::sound::{Audio, Sound};
+    use crate::state::GameEvent;
 
     const IDLE_FRAMES: u8 = 29;
     const RUNNING_FRAMES: u8 = 23;
     const JUMPING_FRAMES: u8 = 35;
     const SLIDING_FRAMES: u8 = 14;
-    const RUNNING_SPEED: i16 = 4;
+    pub const RUNNING_SPEED: i16 = 4;
     const IDLE_FRAME_NAME: &str = "Idle";
-    const RUN_FRAME_NAME: &str = "Run";
+    pub const RUN_FRAME_NAME: &str = "Run";
     const SLIDING_FRAME_NAME: &str = "Slide";
     const JUMPING_FRAME_NAME: &str = "Jump";
-    const JUMP_SPEED: i16 = -25;
+    pub const JUMP_SPEED: i16 = -25;
     const FALLING_FRAMES: u8 = 29;
     const FALLING_FRAME_NAME: &str = "Dead";
     const KNOCKED_OUT_FRAME_NAME: &str = "Dead";
-    const GRAVITY: i16 = 1;
-    const TERMINAL_VELOCITY: i16 = 20;
-    const FLOOR: i16 = 479;
-    const PLAYER_HEIGHT: i16 = HEIGHT - FLOOR;
+    pub const GRAVITY: i16 = 1;
+    pub const TERMINAL_VELOCITY: i16 = 20;
+    pub const FLOOR: i16 = 479;
+    pub const PLAYER_HEIGHT: i16 = HEIGHT - FLOOR;
     const STARTING_POINT: i16 = -20;
 
     #[derive(Clone)]
@@ -166,8 +194,8 @@ pub mod red_hat_boy_states {
         pub frame: u8,
         pub position: Point,
         pub velocity: Point,
-        audio: Audio,
-        jump_sound: Sound,
+        // Emitted by the transitions, until they're taken
+        pub events: Vec<GameEvent>,
     }
 
     impl RedHatBoyContext {
@@ -219,10 +247,8 @@ pub mod red_hat_boy_states {
             self
         }
 
-        fn play_jump_sound(self) -> Self {
-            if let Err(err) = self.audio.play_sound(&self.jump_sound, 0.01) {
-                log!("Error playing jump sound: {}", err);
-            }
+        fn emit(mut self, event: GameEvent) -> Self {
+            self.events.push(event);
             self
         }
     }
@@ -241,13 +267,17 @@ pub mod red_hat_boy_states {
         fn update_context(&mut self, frames: u8) {
             self.context = self.context.clone().update(frames);
         }
+
+        pub fn take_events(&mut self) -> Vec<GameEvent> {
+            std::mem::take(&mut self.context.events)
+        }
     }
 
     #[derive(Copy, Clone)]
     pub struct Idle;
 
     impl RedHatBoyState<Idle> {
-        pub fn new(audio: Audio, jump_sound: Sound) -> Self {
+        pub fn new() -> Self {
             RedHatBoyState {
                 context: RedHatBoyContext {
                     frame: 0,
@@ -256,8 +286,7 @@ pub mod red_hat_boy_states {
                         y: FLOOR,
                     },
                     velocity: Point { x: 0, y: 0 },
-                    audio,
-                    jump_sound,
+                    events: Vec::new(),
                 },
                 _state: Idle {},
             }
@@ -281,7 +310,11 @@ pub mod red_hat_boy_states {
 
         pub fn knock_out(self) -> RedHatBoyState<Falling> {
             RedHatBoyState {
-                context: self.context.reset_frame().set_horizontal_velocity(0),
+                context: self
+                    .context
+                    .reset_frame()
+                    .set_horizontal_velocity(0)
+                    .emit(GameEvent::KnockedOut),
                 _state: Falling {},
             }
         }
@@ -306,21 +339,25 @@ pub mod red_hat_boy_states {
                     .context
                     .reset_frame()
                     .set_vertical_velocity(JUMP_SPEED)
-                    .play_jump_sound(),
+                    .emit(GameEvent::Jumped),
                 _state: Jumping {},
             }
         }
 
         pub fn slide(self) -> RedHatBoyState<Sliding> {
             RedHatBoyState {
-                context: self.context.reset_frame(),
+                context: self.context.reset_frame().emit(GameEvent::Slid),
                 _state: Sliding {},
             }
         }
 
         pub fn knock_out(self) -> RedHatBoyState<Falling> {
             RedHatBoyState {
-                context: self.context.reset_frame().set_horizontal_velocity(0),
+                context: self
+                    .context
+                    .reset_frame()
+                    .set_horizontal_velocity(0)
+                    .emit(GameEvent::KnockedOut),
                 _state: Falling {},
             }
         }
@@ -358,7 +395,11 @@ pub mod red_hat_boy_states {
 
         pub fn land_on(self, position: i16) -> RedHatBoyState<Running> {
             RedHatBoyState {
-                context: self.context.reset_frame().set_on(position as i16),
+                context: self
+                    .context
+                    .reset_frame()
+                    .set_on(position as i16)
+                    .emit(GameEvent::Landed),
                 _state: Running {},
             }
         }
@@ -369,7 +410,8 @@ pub mod red_hat_boy_states {
                     .context
                     .reset_frame()
                     .set_horizontal_velocity(0)
-                    .set_vertical_velocity(0),
+                    .set_vertical_velocity(0)
+                    .emit(GameEvent::KnockedOut),
                 _state: Falling {},
             }
         }
@@ -407,7 +449,11 @@ pub mod red_hat_boy_states {
 
         pub fn knock_out(self) -> RedHatBoyState<Falling> {
             RedHatBoyState {
-                context: self.context.reset_frame().set_horizontal_velocity(0),
+                context: self
+                    .context
+                    .reset_frame()
+                    .set_horizontal_velocity(0)
+                    .emit(GameEvent::KnockedOut),
                 _state: Falling {},
             }
         }
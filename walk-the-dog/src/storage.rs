@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::browser;
+
+pub trait Storage {
+    fn get_item(&self, key: &str) -> Result<Option<String>>;
+    fn set_item(&self, key: &str, value: &str) -> Result<()>;
+}
+
+pub fn load<T: DeserializeOwned>(storage: &dyn Storage, key: &str) -> Result<Option<T>> {
+    match storage.get_item(key)? {
+        Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn save<T: Serialize>(storage: &dyn Storage, key: &str, value: &T) -> Result<()> {
+    storage.set_item(key, &serde_json::to_string(value)?)
+}
+
+pub struct LocalStorage {
+    storage: web_sys::Storage,
+}
+
+impl LocalStorage {
+    pub fn new() -> Result<Self> {
+        Ok(LocalStorage {
+            storage: browser::local_storage()?,
+        })
+    }
+}
+
+impl Storage for LocalStorage {
+    fn get_item(&self, key: &str) -> Result<Option<String>> {
+        self.storage
+            .get_item(key)
+            .map_err(|err| anyhow!("Failed to read {} from localStorage: {:#?}", key, err))
+    }
+
+    fn set_item(&self, key: &str, value: &str) -> Result<()> {
+        self.storage
+            .set_item(key, value)
+            .map_err(|err| anyhow!("Failed to write {} to localStorage: {:#?}", key, err))
+    }
+}
+
+// Keeps everything in memory, for native tests and browsers without localStorage.
+#[derive(Default)]
+pub struct MemoryStorage {
+    items: RefCell<HashMap<String, String>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get_item(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.items.borrow().get(key).cloned())
+    }
+
+    fn set_item(&self, key: &str, value: &str) -> Result<()> {
+        self.items.borrow_mut().insert(key.into(), value.into());
+        Ok(())
+    }
+}